@@ -0,0 +1,45 @@
+use std::env;
+use std::fs::{read_dir, write};
+use std::path::{Path, PathBuf};
+
+// every file under default_notes_templates gets baked into the binary, so
+// adding a template is just dropping the file in. the list ends up in
+// OUT_DIR/bundled_templates.rs, which templates.rs includes.
+const TEMPLATES: &str = "default_notes_templates";
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries =
+        read_dir(dir).unwrap_or_else(|e| panic!("error reading {}!\n{}", dir.display(), e));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEMPLATES);
+    println!("cargo:rerun-if-changed={}", TEMPLATES);
+    let mut files = Vec::new();
+    collect(&root, &mut files);
+    files.sort();
+    let mut generated = format!("const BUNDLED: [(&str, &[u8]); {}] = [\n", files.len());
+    for file in &files {
+        let name = file
+            .strip_prefix(&root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        generated.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            file.display().to_string()
+        ));
+    }
+    generated.push_str("];\n");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("bundled_templates.rs");
+    write(&out, generated).unwrap();
+}
@@ -1,21 +1,60 @@
 use crate::{
-    commands::{ToolArgument, build_args, build_tools},
-    lib::{self, Destination, Message, Project},
-    load_projects, load_settings, print_error, print_success,
+    commands::{ToolArgument, build_args, build_tools, config_command, profile_command},
+    keys::KeyRing,
+    load_projects, load_settings,
+    network::{ConnectionState, ServerRequest},
+    print_error, print_success, project,
+    protocol::{Destination, EventKind, Message, ServerMessage},
+    settings::Overrides,
+    table::Table,
+};
+use colored::Colorize;
+use rustyline::{DefaultEditor, ExternalPrinter};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
 };
-use std::{path::PathBuf, thread::sleep, time::Duration};
 use tokio::{
     self,
-    io::AsyncBufReadExt,
     sync::mpsc::{Receiver, Sender, channel},
+    sync::watch,
 };
 
-pub async fn rec_message(mut console_rx: Receiver<Message>, cli_tx: Sender<Message>) {
+// the active project can change under us, so the prompt is rebuilt every
+// time it gets shown.
+fn build_prompt(config: &Path) -> String {
+    match project::active_project_name(&config.with_file_name("projects")) {
+        Some(name) => format!("[{}] what is thy bidding my master?", name),
+        None => String::from("what is thy bidding my master?"),
+    }
+}
+
+// output can land while the user is half way through typing. going through
+// rustyline's printer clears the line being edited, prints above it and then
+// redraws the prompt with whatever had been typed so far. without a terminal
+// there's no printer and nothing to redraw, so it's just printed.
+fn show<P: ExternalPrinter>(printer: &mut Option<P>, text: String) {
+    if printer.is_some() {
+        let print_res = printer.as_mut().unwrap().print(format!("{}\n", text));
+        if print_res.is_ok() {
+            return;
+        }
+    }
+    println!("{}", text);
+}
+
+pub async fn rec_message<P: ExternalPrinter + Send>(
+    mut console_rx: Receiver<Message>,
+    cli_tx: Sender<Message>,
+    mut printer: Option<P>,
+) {
     print_success("Console output initialized!");
-    let prompt = String::from("what is thy bidding my master?");
     let prompt_message = Message {
         source: Destination::Console,
         destination: Destination::Console,
+        payload: None,
         content: String::from("PROMPT"),
     };
     let mut display = true;
@@ -55,6 +94,7 @@ pub async fn rec_message(mut console_rx: Receiver<Message>, cli_tx: Sender<Messa
                         let done_message = Message {
                             source: Destination::Console,
                             destination: Destination::Console,
+                            payload: None,
                             content: String::from("DONE"),
                         };
                         cli_tx.send(done_message).await.unwrap();
@@ -71,11 +111,7 @@ pub async fn rec_message(mut console_rx: Receiver<Message>, cli_tx: Sender<Messa
                     }
                 }
             } else if message.source == Destination::Server {
-                output.push_str(&format!(
-                    "\n{} recieved from server! (functionality coming soon!)\n",
-                    message.content
-                ));
-                display = true;
+                show(&mut printer, render_server_event(&message));
             } else if message.source == Destination::Console {
                 output.push_str(&format!("\n{}\n", &message.content));
                 display = true;
@@ -84,12 +120,13 @@ pub async fn rec_message(mut console_rx: Receiver<Message>, cli_tx: Sender<Messa
         if display {
             match interactive {
                 false => {
-                    if !exit {
-                        output.push_str(&prompt);
+                    // the prompt itself belongs to rustyline now.
+                    let output = output.trim_matches('\n');
+                    if !exit && !output.is_empty() {
                         if output.contains("error") {
-                            print_error(&output, None);
+                            show(&mut printer, output.red().to_string());
                         } else {
-                            print_success(&output);
+                            show(&mut printer, output.green().to_string());
                         }
                     }
                 }
@@ -108,35 +145,107 @@ pub async fn rec_message(mut console_rx: Receiver<Message>, cli_tx: Sender<Messa
     }
 }
 
-pub async fn console_user_input() -> String {
-    let mut reader = tokio::io::BufReader::new(tokio::io::stdin());
-    let mut response = Vec::new();
-    let _fut = reader.read_until(b'\n', &mut response).await.unwrap();
-    let user_input = match str::from_utf8(&response) {
-        Ok(s) => s.to_string(),
-        Err(_e) => "".to_string(),
+pub fn render_server_event(message: &Message) -> String {
+    if message.payload.is_none() {
+        return format!("[server] {}", message.content);
+    }
+    let payload = message.payload.clone().unwrap();
+    let content = match &payload {
+        ServerMessage::Event { content, .. } => content.clone(),
+        ServerMessage::Error { message, .. } => message.clone(),
+        other => other.describe(),
     };
-    return user_input;
+    match payload.event_kind() {
+        EventKind::CheckIn => format!("[+] agent check-in: {}", content)
+            .bright_cyan()
+            .to_string(),
+        EventKind::TaskResult => format!("[*] task result:\n{}", content).green().to_string(),
+        EventKind::Chat => format!("[chat] {}", content).magenta().to_string(),
+        EventKind::Error => format!("[!] server error: {}", content)
+            .red()
+            .bold()
+            .to_string(),
+        EventKind::Other => format!("[server] {}", content).yellow().to_string(),
+    }
+}
+
+pub async fn watch_server_state(
+    mut state_rx: watch::Receiver<ConnectionState>,
+    console_tx: Sender<Message>,
+) {
+    while state_rx.changed().await.is_ok() {
+        let state = *state_rx.borrow_and_update();
+        let content = match state {
+            ConnectionState::Connected => String::from("connected to the server!"),
+            ConnectionState::Reconnecting => {
+                String::from("error! lost the server connection, reconnecting...")
+            }
+            ConnectionState::Down => String::from("error! the server connection is down!"),
+        };
+        let message = Message {
+            source: Destination::Console,
+            destination: Destination::Console,
+            payload: None,
+            content,
+        };
+        if console_tx.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+// rustyline blocks, so each line is read on the blocking pool. ctrl-c and
+// ctrl-d come back as an empty line.
+pub async fn console_user_input(editor: Arc<Mutex<DefaultEditor>>, prompt: String) -> String {
+    let read_res =
+        tokio::task::spawn_blocking(move || editor.lock().unwrap().readline(&prompt)).await;
+    if read_res.is_err() {
+        return String::new();
+    }
+    read_res.unwrap().unwrap_or_default()
 }
 
 pub async fn cli(
     console_tx: Sender<Message>,
     console_rx: Receiver<Message>,
     main_tx: Sender<Message>,
+    server_tx: Sender<ServerRequest>,
+    state_rx: watch::Receiver<ConnectionState>,
+    keyring: KeyRing,
     config: PathBuf,
+    overrides: Overrides,
     runtime: tokio::runtime::Handle,
 ) {
+    let editor_res = DefaultEditor::new();
+    if editor_res.is_err() {
+        print_error(
+            "error setting up the terminal!",
+            Some(editor_res.err().unwrap().to_string()),
+        );
+        return;
+    }
+    let mut editor = editor_res.unwrap();
+    let printer = editor.create_external_printer().ok();
+    let editor = Arc::new(Mutex::new(editor));
     let (cli_tx, mut cli_rx) = channel(1);
-    let handle = runtime.spawn(rec_message(console_rx, cli_tx));
+    let handle = runtime.spawn(rec_message(console_rx, cli_tx, printer));
+    runtime.spawn(watch_server_state(state_rx.clone(), console_tx.clone()));
     print_success("started the CLI!");
     print_success("happy hacking!");
     loop {
-        let input_handle = tokio::spawn(console_user_input());
-        let mut settings = load_settings(&config, false);
+        let prompt = format!("{} ", build_prompt(&config).green());
+        let user_input = console_user_input(editor.clone(), prompt)
+            .await
+            .trim()
+            .to_string();
+        // loaded once the command is in so anything the last command wrote,
+        // like a newly activated project, is picked up.
+        let settings = load_settings(&config, &overrides, false);
         let mut projects = load_projects(&config, false);
-        let tool_args = build_args(&projects, &config, &settings);
+        let tool_args = build_args(
+            &projects, &config, &settings, &server_tx, &keyring, &overrides,
+        );
         let tool_commands = build_tools(console_tx.clone());
-        let user_input = input_handle.await.unwrap().trim().to_string();
         let mut user_command_name = String::new();
         let mut user_command_args = Vec::new();
         if user_input.contains(" ") {
@@ -150,6 +259,7 @@ pub async fn cli(
             let message = Message {
                 source: Destination::Control,
                 destination: Destination::Console,
+                payload: None,
                 content: String::from("exit"),
             };
             console_tx.send(message).await.unwrap();
@@ -157,6 +267,7 @@ pub async fn cli(
             let message = Message {
                 source: Destination::Console,
                 destination: Destination::Control,
+                payload: None,
                 content: String::from("exit"),
             };
             main_tx.send(message).await.unwrap();
@@ -165,6 +276,7 @@ pub async fn cli(
             let mut message = Message {
                 source: Destination::Console,
                 destination: Destination::Console,
+                payload: None,
                 content: String::new(),
             };
             if user_command_args.len() > 0 {
@@ -175,16 +287,63 @@ pub async fn cli(
                     }
                 }
             } else {
-                let mut help_table = lib::Table::default();
+                let mut help_table = Table::default();
                 let mut data = vec![format!("command|help")];
                 for command in &tool_commands {
                     data.push(format!("{}|{}", command.name, command.help));
                 }
+                data.push("server|status, or send a command to the server".to_string());
+                data.push(
+                    "config|config list, config get <key> or config set <key> <value>".to_string(),
+                );
+                data.push("profile|profile list, profile create <name>, profile copy <from> <to> or profile delete <name>".to_string());
                 help_table.build(data);
                 message.content = help_table.get_table();
                 console_tx.send(message.clone()).await.unwrap();
             }
             continue;
+        } else if user_command_name == "server" {
+            if user_command_args.is_empty() || user_command_args == vec!["status"] {
+                let state = *state_rx.borrow();
+                let message = Message {
+                    source: Destination::Console,
+                    destination: Destination::Console,
+                    payload: None,
+                    content: format!("server connection is {}", state.as_str()),
+                };
+                console_tx.send(message).await.unwrap();
+            } else {
+                let request = ServerRequest::new(user_command_args.join(" "));
+                server_tx.send(request).await.unwrap();
+            }
+            continue;
+        } else if user_command_name == "profile" {
+            let content = match profile_command(&config, &user_command_args) {
+                Ok(output) => output,
+                Err(e) => format!("error! {}", e),
+            };
+            let message = Message {
+                source: Destination::Console,
+                destination: Destination::Console,
+                payload: None,
+                content,
+            };
+            console_tx.send(message).await.unwrap();
+            continue;
+        } else if user_command_name == "config" {
+            // picked up by load_settings at the top of the next loop.
+            let content = match config_command(&config, &overrides, &user_command_args) {
+                Ok(output) => output,
+                Err(e) => format!("error! {}", e),
+            };
+            let message = Message {
+                source: Destination::Console,
+                destination: Destination::Console,
+                payload: None,
+                content,
+            };
+            console_tx.send(message).await.unwrap();
+            continue;
         }
         let mut valid_command = false;
         let mut command_to_run = tool_commands[0].clone();
@@ -198,6 +357,7 @@ pub async fn cli(
             let message = Message {
                 source: Destination::Console,
                 destination: Destination::Console,
+                payload: None,
                 content: String::from("error! invalid command!"),
             };
             console_tx.send(message).await.unwrap();
@@ -212,11 +372,12 @@ pub async fn cli(
         }
         let mut correct_args = false;
         for arg in user_command_args.clone() {
-            if !arg.contains("=") {
-                let mut new_arg = ToolArgument::default();
-                new_arg.name = String::from(arg);
-                new_arg.string = Some(String::from(arg));
-                command_to_run_arg_vec.push(new_arg);
+            if !arg.contains("=") && !arg.starts_with("--") {
+                command_to_run_arg_vec.push(ToolArgument {
+                    name: String::from(arg),
+                    string: Some(String::from(arg)),
+                    ..Default::default()
+                });
             }
         }
         if command_to_run_arg_vec.len()
@@ -224,8 +385,25 @@ pub async fn cli(
         {
             correct_args = true;
         }
+        let mut unknown_flag = String::new();
         for arg in &user_command_args {
-            if arg.contains("=") {
+            // --some-flag turns into a some_flag argument set to true, as long
+            // as the command takes one. a typo'd flag must not run the command
+            // without it, --dryrun silently deleting things is the worst case.
+            if arg.starts_with("--") {
+                let name = arg.trim_start_matches("--").replace('-', "_");
+                if !command_to_run.optional_args.contains(&name) {
+                    if unknown_flag.is_empty() {
+                        unknown_flag = arg.to_string();
+                    }
+                    continue;
+                }
+                command_to_run_arg_vec.push(ToolArgument {
+                    name,
+                    boolean: Some(true),
+                    ..Default::default()
+                });
+            } else if arg.contains("=") {
                 let arg_vec: Vec<&str> = arg.split("=").collect();
                 let mut new_arg = ToolArgument::default();
                 new_arg.name = String::from(arg_vec[0]);
@@ -233,7 +411,31 @@ pub async fn cli(
                 command_to_run_arg_vec.push(new_arg);
             }
         }
-        if correct_args == false {
+        if !unknown_flag.is_empty() {
+            let message = Message {
+                source: Destination::Console,
+                destination: Destination::Console,
+                payload: None,
+                content: format!(
+                    "error! {} doesn't take {}\n{}",
+                    command_to_run.name, unknown_flag, command_to_run.help
+                ),
+            };
+            console_tx.send(message).await.unwrap();
+            continue;
+        }
+        // commands that work on a project fall back to the active one.
+        if command_to_run.takes_project
+            && !command_to_run_arg_vec.iter().any(|arg| arg.name == "name")
+            && let Some(active) = project::active_project(&projects)
+        {
+            command_to_run_arg_vec.push(ToolArgument {
+                name: String::from("name"),
+                string: Some(active.name.clone()),
+                ..Default::default()
+            });
+        }
+        if !correct_args {
             println!("{}", command_to_run.help);
             print_error(
                 "wrong number of arguments supplied!\n please read the above help message.",
@@ -252,16 +454,11 @@ pub async fn cli(
         if command_to_run_arg_vec.len() > 0 {
             command_to_run.args = Some(command_to_run_arg_vec.clone());
         }
+        // the optionally interactive commands only ever prompt for the
+        // project name, so they run straight through once they have one.
         if command_to_run.optionally_interactive {
-            if command_to_run_arg_vec.len()
-                == command_to_run.req_args.len()
-                    + command_to_run.user_args.len()
-                    + command_to_run.optional_args.len()
-            {
-                command_to_run.interactive = false;
-            } else {
-                command_to_run.interactive = true;
-            }
+            command_to_run.interactive =
+                !command_to_run_arg_vec.iter().any(|arg| arg.name == "name");
         }
         if command_to_run.interactive {
             println!("we got to the interactive section!");
@@ -270,6 +467,7 @@ pub async fn cli(
             let init_message = Message {
                 source: Destination::Control,
                 destination: Destination::Console,
+                payload: None,
                 content: String::from("ack"),
             };
             let mut inited = false;
@@ -287,18 +485,20 @@ pub async fn cli(
                                 .send(Message {
                                     source: Destination::Console,
                                     destination: Destination::Console,
+                                    payload: None,
                                     content: String::from("ready"),
                                 })
                                 .await
                                 .unwrap();
                         }
                         "PROMPT" => {
-                            let input_handle = tokio::spawn(console_user_input());
-                            let response = input_handle.await.unwrap();
+                            let response =
+                                console_user_input(editor.clone(), String::from("> ")).await;
                             let tx_res = command_tx
                                 .send(Message {
                                     source: Destination::Console,
                                     destination: Destination::Console,
+                                    payload: None,
                                     content: response.trim().to_string(),
                                 })
                                 .await;
@@ -1,18 +1,25 @@
+use crate::doctor;
 use crate::get_user_input;
-use crate::lib::Destination;
-use crate::lib::Message;
-use crate::lib::Project;
-use crate::lib::Table;
+use crate::keys;
+use crate::keys::KeyRing;
+use crate::network::ServerRequest;
 use crate::print_error;
 use crate::print_success;
+use crate::profiles;
+use crate::project;
+use crate::project::{PROJECT_INFO_KEYS, Project, Stage};
+use crate::protocol::Destination;
+use crate::protocol::Message;
+use crate::settings::{self, Overrides, Settings, Source};
+use crate::table::Table;
+use crate::templates;
 use dns_lookup::lookup_host;
-use std::collections::HashMap;
 use std::fmt::Arguments;
 use std::fs::create_dir;
 use std::fs::create_dir_all;
 use std::fs::{File, OpenOptions, ReadDir, read_to_string};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Output;
 use std::process::exit;
 use std::result;
@@ -33,6 +40,7 @@ pub struct ToolCommand {
     pub args: Option<Vec<ToolArgument>>,
     pub interactive: bool,
     pub optionally_interactive: bool,
+    pub takes_project: bool,
     pub func: fn(
         Option<Vec<ToolArgument>>,
         Sender<Message>,
@@ -62,6 +70,7 @@ impl ToolCommand {
             optional_args: Vec::new(),
             interactive: false,
             optionally_interactive: false,
+            takes_project: false,
             args: None,
             func: func,
         }
@@ -76,6 +85,7 @@ impl ToolCommand {
             let message = Message {
                 source: Destination::Console,
                 destination: Destination::Control,
+                payload: None,
                 content: String::from("interactive"),
             };
             self.console_tx.send(message).await.unwrap();
@@ -84,25 +94,25 @@ impl ToolCommand {
             runtime.spawn_blocking(move || {
                 (self.func)(self.args, self.console_tx, Some(command_tx), execute_rx)
             });
-            loop {
-                let rx_res = command_rx.try_recv();
-                if rx_res.is_ok() {
-                    let message = rx_res.unwrap();
-                    match message.content.as_str() {
-                        "noninteractive" => {
-                            self.interactive = false;
-                            console_tx.send(message).await.unwrap();
-                        }
-                        "finished" => {
-                            console_tx.send(message).await.unwrap();
-                            break;
-                        }
-                        _ => {}
+            // ends on "finished" or once the command returns and drops its
+            // end of the channel.
+            while let Some(message) = command_rx.recv().await {
+                match message.content.as_str() {
+                    "noninteractive" => {
+                        self.interactive = false;
+                        console_tx.send(message).await.unwrap();
                     }
+                    "finished" => {
+                        console_tx.send(message).await.unwrap();
+                        break;
+                    }
+                    _ => {}
                 }
             }
         } else {
-            runtime.spawn(async move { (self.func)(self.args, self.console_tx, None, None) });
+            // the commands are plain functions that block on file copies,
+            // distrobox and blocking_send, none of which an async worker allows.
+            runtime.spawn_blocking(move || (self.func)(self.args, self.console_tx, None, None));
         }
     }
 }
@@ -120,17 +130,21 @@ pub struct ToolArgument {
     pub boolean: Option<bool>,
     pub console_tx: Option<Sender<String>>,
     pub tx: Option<Sender<String>>,
+    pub server: Option<Sender<ServerRequest>>,
+    pub keyring: Option<KeyRing>,
+    pub overrides: Option<Overrides>,
 }
 
 pub fn build_tools(tx: Sender<Message>) -> Vec<ToolCommand> {
     let mut tool_commands = Vec::new();
     let mut list_projects = ToolCommand::new(
         "list_projects".to_string(),
-        "lists the currently tracked projects".to_string(),
+        "lists the currently tracked projects. optionally takes stage= to only show upcoming, current, completed or archived ones".to_string(),
         tx.clone(),
         list_projects,
     );
     list_projects.req_args = vec![String::from("projects")];
+    list_projects.optional_args = vec![String::from("stage")];
     list_projects.interactive = false;
     tool_commands.push(list_projects);
     let mut new_project_command = ToolCommand::new("new_project".to_string(), "creates a new project. you can give it a project name as an argument, or it will prompt you for the name.".to_string(), tx.clone(), new_project);
@@ -151,7 +165,7 @@ pub fn build_tools(tx: Sender<Message>) -> Vec<ToolCommand> {
     tool_commands.push(new_project_command);
     let mut promote_project_command = ToolCommand::new(
         "promote_project".to_string(),
-        "promote a project to be promoted from upcoming to curent. Optionally takes a name= argument and a home= argument.".to_string(),
+        "promote a project to be promoted from upcoming to curent. Optionally takes a name= argument, defaulting to the active project, and a home= argument.".to_string(),
         tx.clone(),
         promote_project,
     );
@@ -163,14 +177,135 @@ pub fn build_tools(tx: Sender<Message>) -> Vec<ToolCommand> {
     ];
     promote_project_command.optionally_interactive = true;
     promote_project_command.optional_args = vec![String::from("name"), String::from("home")];
+    promote_project_command.takes_project = true;
     tool_commands.push(promote_project_command);
+    let mut key_fingerprint_command = ToolCommand::new(
+        "key_fingerprint".to_string(),
+        "shows the fingerprint of the client key, and of the previous key if it is still in its grace period.".to_string(),
+        tx.clone(),
+        key_fingerprint,
+    );
+    key_fingerprint_command.req_args = vec![String::from("keyring")];
+    tool_commands.push(key_fingerprint_command);
+    let mut export_key_command = ToolCommand::new(
+        "export_key".to_string(),
+        "exports the client key wrapped with a transfer passphrase for the server operator. takes passphrase= and optionally path=".to_string(),
+        tx.clone(),
+        export_key,
+    );
+    export_key_command.req_args = vec![String::from("keyring"), String::from("config")];
+    export_key_command.optional_args = vec![String::from("passphrase"), String::from("path")];
+    tool_commands.push(export_key_command);
+    let mut import_key_command = ToolCommand::new(
+        "import_key".to_string(),
+        "imports a key issued by the server. takes path= and passphrase=, optionally grace= in hours to keep the old key around.".to_string(),
+        tx.clone(),
+        import_key,
+    );
+    import_key_command.req_args = vec![String::from("keyring")];
+    import_key_command.optional_args = vec![
+        String::from("path"),
+        String::from("passphrase"),
+        String::from("grace"),
+    ];
+    tool_commands.push(import_key_command);
+    let mut rotate_key_command = ToolCommand::new(
+        "rotate_key".to_string(),
+        "generates a new client key and hands it to the server. optionally takes grace= in hours to keep the old key around.".to_string(),
+        tx.clone(),
+        rotate_key,
+    );
+    rotate_key_command.req_args = vec![String::from("keyring"), String::from("server")];
+    rotate_key_command.optional_args = vec![String::from("grace")];
+    tool_commands.push(rotate_key_command);
+    let mut doctor_command = ToolCommand::new(
+        "doctor".to_string(),
+        "checks the configured paths, key, distrobox setup and server connection and reports anything wrong.".to_string(),
+        tx.clone(),
+        doctor,
+    );
+    doctor_command.req_args = vec![String::from("config"), String::from("overrides")];
+    tool_commands.push(doctor_command);
+    let mut project_info_command = ToolCommand::new(
+        "project_info".to_string(),
+        "shows or sets a project's engagement details. takes name=, defaulting to the active project, and optionally client=, engagement_type=, start_date= and end_date= (YYYY-MM-DD), testers= and tags= (comma separated), time_zone= and point_of_contact=".to_string(),
+        tx.clone(),
+        project_info,
+    );
+    project_info_command.req_args = vec![String::from("projects")];
+    project_info_command.optional_args = vec![String::from("name")];
+    for key in PROJECT_INFO_KEYS {
+        project_info_command.optional_args.push(String::from(key));
+    }
+    project_info_command.takes_project = true;
+    tool_commands.push(project_info_command);
+    let mut complete_project_command = ToolCommand::new(
+        "complete_project".to_string(),
+        "marks a current project as completed once testing is done. takes name=, defaulting to the active project.".to_string(),
+        tx.clone(),
+        complete_project,
+    );
+    complete_project_command.req_args = vec![String::from("projects")];
+    complete_project_command.optional_args = vec![String::from("name")];
+    complete_project_command.takes_project = true;
+    tool_commands.push(complete_project_command);
+    let mut archive_project_command = ToolCommand::new(
+        "archive_project".to_string(),
+        "removes a current or completed project's distrobox and packs its files and notes into a dated archive with a sha256 manifest under the archive setting. takes name=, defaulting to the active project.".to_string(),
+        tx.clone(),
+        archive_project,
+    );
+    archive_project_command.req_args = vec![
+        String::from("projects"),
+        String::from("config"),
+        String::from("overrides"),
+    ];
+    archive_project_command.optional_args = vec![String::from("name")];
+    archive_project_command.takes_project = true;
+    tool_commands.push(archive_project_command);
+    let mut remove_project_command = ToolCommand::new(
+        "remove_project".to_string(),
+        "deletes a project's folders, config and distrobox after showing exactly what will go and having you type the name back. takes name=, defaulting to the active project, and --dry-run to only show what would be deleted.".to_string(),
+        tx.clone(),
+        remove_project,
+    );
+    remove_project_command.req_args = vec![
+        String::from("projects"),
+        String::from("config"),
+        String::from("overrides"),
+    ];
+    remove_project_command.optional_args = vec![String::from("name"), String::from("dry_run")];
+    remove_project_command.interactive = true;
+    remove_project_command.takes_project = true;
+    tool_commands.push(remove_project_command);
+    let mut activate_command = ToolCommand::new(
+        "activate".to_string(),
+        "makes name= the active project, which commands that take a project use when no name= is given. name= with no value clears it, no arguments shows the active project.".to_string(),
+        tx.clone(),
+        activate_project,
+    );
+    activate_command.req_args = vec![String::from("projects")];
+    activate_command.optional_args = vec![String::from("name")];
+    tool_commands.push(activate_command);
+    let mut upgrade_templates_command = ToolCommand::new(
+        "upgrade_templates".to_string(),
+        "installs any new bundled note templates. templates you've edited are left alone."
+            .to_string(),
+        tx.clone(),
+        upgrade_templates,
+    );
+    upgrade_templates_command.req_args = vec![String::from("config")];
+    tool_commands.push(upgrade_templates_command);
     return tool_commands;
 }
 
 pub fn build_args(
     projects: &Vec<Project>,
     config: &PathBuf,
-    settings: &HashMap<String, String>,
+    settings: &Settings,
+    server_tx: &Sender<ServerRequest>,
+    keyring: &KeyRing,
+    overrides: &Overrides,
 ) -> Vec<ToolArgument> {
     let mut args = Vec::new();
     for project in projects {
@@ -189,64 +324,139 @@ pub fn build_args(
     config_arg.name = String::from("config");
     config_arg.path = Some(config.clone());
     args.push(config_arg);
-    for setting in settings.keys() {
-        match setting.as_str() {
-            "templatebox" => {
-                let mut template_arg = ToolArgument::default();
-                template_arg.name = String::from("templatebox");
-                let boxname = settings.get("templatebox").unwrap().clone();
-                template_arg.string = Some(boxname);
-                args.push(template_arg);
-            }
-            "current_notes" => {
-                let mut current_note_arg = ToolArgument::default();
-                current_note_arg.name = String::from("current_notes");
-                let path = PathBuf::from(settings.get("current_notes").unwrap());
-                current_note_arg.path = Some(path);
-                args.push(current_note_arg);
-            }
-            "current_files" => {
-                let mut new_arg = ToolArgument::default();
-                new_arg.name = String::from("current_files");
-                let path = PathBuf::from(settings.get("current_files").unwrap());
-                new_arg.path = Some(path);
-                args.push(new_arg);
+    args.push(ToolArgument {
+        name: String::from("server"),
+        server: Some(server_tx.clone()),
+        ..Default::default()
+    });
+    args.push(ToolArgument {
+        name: String::from("keyring"),
+        keyring: Some(keyring.clone()),
+        ..Default::default()
+    });
+    args.push(ToolArgument {
+        name: String::from("overrides"),
+        overrides: Some(overrides.clone()),
+        ..Default::default()
+    });
+    if settings.templatebox.is_some() {
+        args.push(ToolArgument {
+            name: String::from("templatebox"),
+            string: settings.templatebox.clone(),
+            ..Default::default()
+        });
+    }
+    let paths = [
+        ("current_notes", &settings.current_notes),
+        ("current_files", &settings.current_files),
+        ("upcoming_files", &settings.upcoming_files),
+        ("upcoming_notes", &settings.upcoming_notes),
+        ("archive", &settings.archive),
+        ("tools", &settings.tools),
+    ];
+    for (name, path) in paths {
+        if path.is_some() {
+            args.push(ToolArgument {
+                name: String::from(name),
+                path: path.clone(),
+                ..Default::default()
+            });
+        }
+    }
+    args.push(ToolArgument {
+        name: String::from("distrobox"),
+        boolean: Some(settings.distrobox),
+        ..Default::default()
+    });
+    args
+}
+
+// backs the config command in the cli. list, get <key> or set <key> <value>.
+pub fn config_command(
+    config: &Path,
+    overrides: &Overrides,
+    args: &[&str],
+) -> Result<String, String> {
+    let usage = String::from("usage: config list | config get <key> | config set <key> <value>");
+    let settings = Settings::load_with(config, overrides)?;
+    let file_keys = settings::file_keys(config);
+    if args.is_empty() || args[0] == "list" {
+        let mut lines = vec![String::from("setting|value|from")];
+        for key in settings::KEYS {
+            lines.push(format!(
+                "{}|{}|{}",
+                key,
+                settings.get(key).unwrap_or_default(),
+                overrides.source(key, &file_keys).as_str()
+            ));
+        }
+        let mut table = Table::default();
+        table.build(lines);
+        return Ok(table.get_table());
+    }
+    match args[0] {
+        "get" => {
+            if args.len() != 2 {
+                return Err(usage);
             }
-            "upcoming_files" => {
-                let mut new_arg = ToolArgument::default();
-                new_arg.name = String::from("upcoming_files");
-                let path = PathBuf::from(settings.get("upcoming_files").unwrap());
-                new_arg.path = Some(path);
-                args.push(new_arg);
+            if !settings::KEYS.contains(&args[1]) {
+                return Err(format!("unknown setting {}", args[1]));
             }
-            "upcoming_notes" => {
-                let mut new_arg = ToolArgument::default();
-                new_arg.name = String::from("upcoming_notes");
-                let path = PathBuf::from(settings.get("upcoming_notes").unwrap());
-                new_arg.path = Some(path);
-                args.push(new_arg);
+            Ok(format!(
+                "{} ({})",
+                settings.get(args[1]).unwrap_or(String::from("(not set)")),
+                overrides.source(args[1], &file_keys).as_str()
+            ))
+        }
+        "set" => {
+            if args.len() < 3 {
+                return Err(usage);
             }
-            "tools" => {
-                let mut new_arg = ToolArgument::default();
-                new_arg.name = String::from("tools");
-                let path = PathBuf::from(settings.get("tools").unwrap());
-                new_arg.path = Some(path);
-                args.push(new_arg);
+            let key = args[1];
+            let value = args[2..].join(" ");
+            if !settings::KEYS.contains(&key) {
+                return Err(format!("unknown setting {}", key));
             }
-            "distrobox" => {
-                let mut new_arg = ToolArgument::default();
-                new_arg.name = String::from("distrobox");
-                if settings.get("distrobox").unwrap().contains("yes") {
-                    new_arg.boolean = Some(true);
-                } else {
-                    new_arg.boolean = Some(false);
-                }
-                args.push(new_arg);
+            Settings::check(key, &value)?;
+            // only the file gets written, overrides stay out of it.
+            let mut file_settings = Settings::read(config)?;
+            file_settings.set(key, &value)?;
+            file_settings.validate()?;
+            file_settings.save(config)?;
+            let mut output = format!(
+                "{} set to {}",
+                key,
+                file_settings.get(key).unwrap_or(String::from("(not set)"))
+            );
+            let source = overrides.source(key, &file_keys);
+            if source == Source::Env || source == Source::Flag {
+                output.push_str(&format!(
+                    "\nthis run is still using the {} value, {}",
+                    source.as_str(),
+                    settings.get(key).unwrap_or_default()
+                ));
             }
-            _ => {}
+            Ok(output)
         }
+        _ => Err(usage),
+    }
+}
+
+// backs the profile command in the cli.
+pub fn profile_command(config: &Path, args: &[&str]) -> Result<String, String> {
+    let usage = String::from(
+        "usage: profile list | profile create <name> | profile copy <from> <to> | profile delete <name>",
+    );
+    if args.is_empty() || args[0] == "list" {
+        return profiles::list(config);
+    }
+    match (args[0], args.len()) {
+        ("create", 2) => profiles::create(config, args[1]),
+        ("copy", 3) => profiles::copy_profile(config, args[1], args[2]),
+        ("delete", 2) => profiles::delete(config, args[1], false),
+        ("delete", 3) => profiles::delete(config, args[1], args[2] == "yes"),
+        _ => Err(usage),
     }
-    return args;
 }
 
 pub async fn send_command_output(tx: Sender<Message>, message: Message) {
@@ -260,25 +470,49 @@ pub fn list_projects(
     _rx: Option<Receiver<Message>>,
 ) {
     let given_args = args.unwrap();
-    let mut lines = vec![String::from("name|stage|boxname")];
+    let mut projects = Vec::new();
+    let mut stage = None;
     for arg in given_args {
-        if arg.name == String::from("projects") {
-            for project in arg.projects.unwrap() {
-                if project.current {
-                    let line = format!("{}|{}|{}", project.name, "current", project.boxname);
-                    lines.push(line);
-                } else {
-                    let line = format!("{}|{}|{}", project.name, "upcomming", project.boxname);
-                    lines.push(line);
-                }
-            }
+        match arg.name.as_str() {
+            "projects" => projects = arg.projects.unwrap(),
+            "stage" => stage = arg.string,
+            _ => {}
+        }
+    }
+    let mut filter = None;
+    if let Some(stage) = stage {
+        let stage_res = Stage::parse(&stage);
+        if stage_res.is_err() {
+            let content = format!("error! {}", stage_res.err().unwrap());
+            tokio::spawn(send_command_output(tx, output_message(content)));
+            return;
+        }
+        filter = Some(stage_res.unwrap());
+    }
+    let mut lines = vec![String::from("name|stage|boxname|active")];
+    for project in projects {
+        if filter.is_some() && filter != Some(project.stage) {
+            continue;
+        }
+        let mut marker = "";
+        if project.active {
+            marker = "*";
         }
+        let line = format!(
+            "{}|{}|{}|{}",
+            project.name,
+            project.stage.as_str(),
+            project.boxname,
+            marker
+        );
+        lines.push(line);
     }
     let mut table = Table::default();
     table.build(lines);
     let message = Message {
         source: Destination::Console,
         destination: Destination::Console,
+        payload: None,
         content: table.get_table(),
     };
     tokio::spawn(send_command_output(tx, message.clone()));
@@ -300,11 +534,13 @@ pub fn new_project(
     let non_interactive_message = Message {
         source: Destination::Control,
         destination: Destination::Console,
+        payload: None,
         content: String::from("noninteractive"),
     };
     let mut interactive_message = Message {
         source: Destination::Control,
         destination: Destination::Console,
+        payload: None,
         content: String::from("interactive"),
     };
     for arg in given_args {
@@ -334,6 +570,7 @@ pub fn new_project(
         let message = Message {
             source: Destination::Console,
             destination: Destination::Console,
+            payload: None,
             content: String::from("project_name?"),
         };
         let mut rx = initialize_interactive(rx, tx.clone());
@@ -361,6 +598,7 @@ pub fn new_project(
         let message = Message {
             source: Destination::Console,
             destination: Destination::Console,
+            payload: None,
             content: format!("error! unable to create {}", &project_path.display()),
         };
         tokio::spawn(send_command_output(tx.clone(), message));
@@ -381,6 +619,7 @@ pub fn new_project(
         let message = Message {
             source: Destination::Console,
             destination: Destination::Console,
+            payload: None,
             content: format!(
                 "Error failure to create project files folder!\n{}",
                 files_dir_res.err().unwrap()
@@ -400,6 +639,7 @@ pub fn new_project(
         let message = Message {
             source: Destination::Console,
             destination: Destination::Console,
+            payload: None,
             content: format!(
                 "Error failure to create project files folder!\n{}",
                 files_dir_res.err().unwrap()
@@ -419,7 +659,7 @@ pub fn new_project(
     new_project.name = name.clone();
     new_project.files = files_path;
     new_project.notes = notes_path;
-    new_project.current = false;
+    new_project.stage = Stage::Upcoming;
     new_project.boxname = format!("{}_{}", template_box, name);
     new_project.config = project_path;
     println!("{}", new_project.config.display());
@@ -430,12 +670,14 @@ pub fn new_project(
     let message = Message {
         source: Destination::Console,
         destination: Destination::Console,
+        payload: None,
         content: res,
     };
     tokio::spawn(send_command_output(tx.clone(), message));
     let message = Message {
         source: Destination::Console,
         destination: Destination::Console,
+        payload: None,
         content: String::from("finished"),
     };
     tokio::spawn(send_command_output(tx.clone(), message));
@@ -482,11 +724,7 @@ pub fn promote_project(
             let mut line = format!("{}|", id);
             id += 1;
             line.push_str(&project.name);
-            if project.current {
-                line.push_str("|current");
-            } else {
-                line.push_str("|upcoming");
-            }
+            line.push_str(&format!("|{}", project.stage.as_str()));
             lines.push(line);
         }
         let mut project_table = Table::default();
@@ -495,6 +733,7 @@ pub fn promote_project(
         let table_message = Message {
             source: Destination::Console,
             destination: Destination::Console,
+            payload: None,
             content: projects_string,
         };
         tx.blocking_send(table_message).unwrap();
@@ -515,6 +754,7 @@ pub fn promote_project(
             tx.blocking_send(Message {
                 source: Destination::Console,
                 destination: Destination::Console,
+                payload: None,
                 content: promote_res,
             })
             .unwrap();
@@ -522,32 +762,114 @@ pub fn promote_project(
     }
 }
 
-pub fn remove_project(args: Option<Vec<ToolArgument>>) -> String {
-    let mut project = Project::default();
+// always runs interactively, the project name has to be typed back before
+// anything is deleted. --dry-run just shows what would go.
+pub fn remove_project(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    rx: Option<Receiver<Message>>,
+) {
     let mut projects = Vec::new();
-    if args.is_some() {
-        let given_args = args.unwrap();
-        for arg in given_args {
-            match arg.name.as_str() {
-                "project" => project = arg.project.unwrap(),
-                "projects" => projects = arg.projects.unwrap(),
-                _ => {}
-            }
+    let mut name = None;
+    let mut config = PathBuf::new();
+    let mut overrides = Overrides::default();
+    let mut dry_run = false;
+    for arg in args.unwrap() {
+        match arg.name.as_str() {
+            "projects" => projects = arg.projects.unwrap(),
+            "name" => name = arg.string,
+            "config" => config = arg.path.unwrap(),
+            "overrides" => overrides = arg.overrides.unwrap(),
+            "dry_run" => dry_run = arg.boolean.unwrap_or(false),
+            _ => {}
         }
     }
-    if !project.name.len() > 1 {
-        let mut current_index = 0;
-        for project in &projects {
-            println!("{}.) {}", current_index, project.name);
-            current_index += 1;
+    let rx = initialize_interactive(rx, tx.clone());
+    let finish = |content: String| {
+        deinitialize_interactive(tx.clone());
+        tx.blocking_send(output_message(content)).unwrap();
+    };
+    let settings_res = Settings::load_with(&config, &overrides);
+    if settings_res.is_err() {
+        finish(format!("error! {}", settings_res.err().unwrap()));
+        return;
+    }
+    let settings = settings_res.unwrap();
+    let mut rx = rx;
+    if name.is_none() {
+        let mut lines = vec![String::from("id|name|stage")];
+        for (id, project) in projects.iter().enumerate() {
+            lines.push(format!(
+                "{}|{}|{}",
+                id,
+                project.name,
+                project.stage.as_str()
+            ));
         }
-        let response: usize = get_user_input("project to remove?").parse().unwrap();
-        if response >= projects.len() {
-            return format!("error invalid project selection!");
+        let mut table = Table::default();
+        table.build(lines);
+        let (response, new_rx) = prompt_interactive(
+            rx,
+            tx.clone(),
+            &format!("{}\nproject to remove?", table.get_table()),
+        );
+        rx = new_rx;
+        let selection_res = response.trim().parse::<usize>();
+        if selection_res.is_err() || *selection_res.as_ref().unwrap() >= projects.len() {
+            finish(format!(
+                "error! {} isn't one of the ids above",
+                response.trim()
+            ));
+            return;
         }
-        project = projects[response].clone()
+        name = Some(projects[selection_res.unwrap()].name.clone());
+    }
+    let project_res = find_project(projects, name);
+    if project_res.is_err() {
+        finish(format!("error! {}", project_res.err().unwrap()));
+        return;
+    }
+    let project = project_res.unwrap();
+    let template = settings.templatebox.clone().unwrap_or_default();
+    let remove_box = settings.distrobox && project.boxname != "none" && project.boxname != template;
+    let plan_res = project.removal_plan(remove_box, &configured_roots(&settings));
+    if plan_res.is_err() {
+        finish(format!("error! {}", plan_res.err().unwrap()));
+        return;
     }
-    return project.remove_project();
+    let plan = plan_res.unwrap();
+    let mut lines = vec![String::from("action|target")];
+    for removal in &plan {
+        lines.push(removal.describe());
+    }
+    let mut table = Table::default();
+    table.build(lines);
+    if dry_run {
+        finish(format!(
+            "{}\ndry run, nothing was deleted",
+            table.get_table()
+        ));
+        return;
+    }
+    let (response, _rx) = prompt_interactive(
+        rx,
+        tx.clone(),
+        &format!(
+            "{}\ntype the project name ({}) to go ahead, anything else cancels",
+            table.get_table(),
+            project.name
+        ),
+    );
+    if response.trim() != project.name {
+        finish(String::from("cancelled, nothing was deleted"));
+        return;
+    }
+    let mut content = project.remove_project(&plan);
+    if content == "Success!" {
+        content = format!("{} removed", project.name);
+    }
+    finish(content);
 }
 
 pub fn subdomain_brute(args: Option<Vec<ToolArgument>>) -> String {
@@ -635,31 +957,407 @@ pub fn subdomain_brute(args: Option<Vec<ToolArgument>>) -> String {
     return result_string;
 }
 
-pub fn activate_project(args: Option<Vec<ToolArgument>>) -> String {
-    let mut result_string = String::from("Failed to activeate project!");
+pub fn activate_project(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut projects = Vec::new();
+    let mut name = None;
+    for arg in args.unwrap() {
+        match arg.name.as_str() {
+            "projects" => projects = arg.projects.unwrap(),
+            "name" => name = arg.string,
+            _ => {}
+        }
+    }
+    if name.is_none() {
+        let content = match project::active_project(&projects) {
+            Some(project) => format!("{} is the active project", project.name),
+            None => String::from("no project is active, pick one with activate name=<project>"),
+        };
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    let name = name.unwrap().trim().to_string();
+    if !name.is_empty() && !projects.iter().any(|project| project.name == name) {
+        let content = format!("error! no project named {}", name);
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    // only rewrite the configs whose flag actually changes.
+    for mut project in projects {
+        let active = project.name == name;
+        if project.active == active {
+            continue;
+        }
+        project.active = active;
+        let save_res = project.save_project();
+        if save_res != "Success!" {
+            tokio::spawn(send_command_output(tx, output_message(save_res)));
+            return;
+        }
+    }
+    let content = if name.is_empty() {
+        String::from("no project is active now")
+    } else {
+        format!("{} is now the active project", name)
+    };
+    tokio::spawn(send_command_output(tx, output_message(content)));
+}
+
+fn find_project(projects: Vec<Project>, name: Option<String>) -> Result<Project, String> {
+    if name.is_none() {
+        return Err(String::from(
+            "which project? pass it as name=<project> or activate one first",
+        ));
+    }
+    let name = name.unwrap();
+    let found = projects.into_iter().find(|project| project.name == name);
+    if found.is_none() {
+        return Err(format!("no project named {}", name));
+    }
+    Ok(found.unwrap())
+}
+
+pub fn complete_project(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
     let mut projects = Vec::new();
-    let mut activate_target = String::new();
+    let mut name = None;
     for arg in args.unwrap() {
-        if arg.user_supplied {
-            if arg.name == "project" {
-                activate_target = arg.string.unwrap();
+        match arg.name.as_str() {
+            "projects" => projects = arg.projects.unwrap(),
+            "name" => name = arg.string,
+            _ => {}
+        }
+    }
+    let project_res = find_project(projects, name);
+    if project_res.is_err() {
+        let content = format!("error! {}", project_res.err().unwrap());
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    let mut project = project_res.unwrap();
+    if project.stage != Stage::Current {
+        let content = format!(
+            "error! {} is {}, only current projects can be completed",
+            project.name,
+            project.stage.as_str()
+        );
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    project.stage = Stage::Completed;
+    let mut content = project.save_project();
+    if content == "Success!" {
+        content = format!(
+            "{} marked completed, archive it with archive_project",
+            project.name
+        );
+    }
+    tokio::spawn(send_command_output(tx, output_message(content)));
+}
+
+pub fn archive_project(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut projects = Vec::new();
+    let mut name = None;
+    let mut config = PathBuf::new();
+    let mut overrides = Overrides::default();
+    for arg in args.unwrap() {
+        match arg.name.as_str() {
+            "projects" => projects = arg.projects.unwrap(),
+            "name" => name = arg.string,
+            "config" => config = arg.path.unwrap(),
+            "overrides" => overrides = arg.overrides.unwrap(),
+            _ => {}
+        }
+    }
+    let project_res = find_project(projects, name);
+    if project_res.is_err() {
+        let content = format!("error! {}", project_res.err().unwrap());
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    let mut project = project_res.unwrap();
+    let settings_res = Settings::load_with(&config, &overrides);
+    if settings_res.is_err() {
+        let content = format!("error! {}", settings_res.err().unwrap());
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    let settings = settings_res.unwrap();
+    if settings.archive.is_none() {
+        let content = String::from(
+            "error! there's nowhere to archive to, set one with config set archive <path>",
+        );
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    let protected = configured_roots(&settings);
+    let archive = settings.archive.unwrap();
+    let distrobox = settings.distrobox;
+    let template = settings.templatebox.unwrap_or_default();
+    // shells out to distrobox and can chew on a big project for a while.
+    tokio::spawn(async move {
+        let archive_res = tokio::task::spawn_blocking(move || {
+            project
+                .archive_project(&archive, distrobox, &template, &protected)
+                .map(|path| format!("{} archived to {}", project.name, path.display()))
+        })
+        .await;
+        let content = match archive_res {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => format!("error archiving project!\n{}", e),
+            Err(e) => format!("error archiving project!\n{}", e),
+        };
+        send_command_output(tx, output_message(content)).await;
+    });
+}
+
+// the folders every project lives under, none of them belong to any one
+// project so removing or archiving must never take them along.
+fn configured_roots(settings: &Settings) -> Vec<PathBuf> {
+    [
+        &settings.current_files,
+        &settings.current_notes,
+        &settings.upcoming_files,
+        &settings.upcoming_notes,
+        &settings.archive,
+        &settings.tools,
+    ]
+    .into_iter()
+    .flatten()
+    .cloned()
+    .collect()
+}
+
+fn output_message(content: String) -> Message {
+    Message {
+        source: Destination::Console,
+        destination: Destination::Console,
+        payload: None,
+        content,
+    }
+}
+
+fn grace_period(hours: Option<String>) -> Result<Duration, String> {
+    if hours.is_none() {
+        return Ok(Duration::from_secs(keys::DEFAULT_GRACE_HOURS * 3600));
+    }
+    let hours_res = hours.unwrap().parse::<u64>();
+    if hours_res.is_err() {
+        return Err(String::from(
+            "error! grace= must be a whole number of hours",
+        ));
+    }
+    Ok(Duration::from_secs(hours_res.unwrap() * 3600))
+}
+
+pub fn key_fingerprint(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut keyring = None;
+    for arg in args.unwrap() {
+        if arg.name == "keyring" {
+            keyring = arg.keyring;
+        }
+    }
+    let keyring = keyring.unwrap();
+    let mut lines = vec![String::from("key|fingerprint|expires")];
+    lines.push(format!(
+        "current|{}|never",
+        keys::fingerprint(&keyring.current())
+    ));
+    if let Some((old_key, expires)) = keyring.previous() {
+        lines.push(format!(
+            "previous|{}|{} (unix time)",
+            keys::fingerprint(&old_key),
+            expires
+        ));
+    }
+    let mut table = Table::default();
+    table.build(lines);
+    tokio::spawn(send_command_output(tx, output_message(table.get_table())));
+}
+
+pub fn export_key(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut keyring = None;
+    let mut passphrase = String::new();
+    let mut path = None;
+    for arg in args.unwrap() {
+        match arg.name.as_str() {
+            "keyring" => keyring = arg.keyring,
+            "passphrase" => passphrase = arg.string.unwrap_or_default(),
+            "path" => path = arg.string.map(PathBuf::from),
+            "config" if path.is_none() => {
+                let mut default_path = arg.path.unwrap();
+                default_path.pop();
+                default_path.push("key.export");
+                path = Some(default_path);
             }
+            _ => {}
         }
+    }
+    if passphrase.is_empty() {
+        let message = output_message(String::from(
+            "error! export_key needs a passphrase= to wrap the key with, share it with the server operator separately",
+        ));
+        tokio::spawn(send_command_output(tx, message));
+        return;
+    }
+    let key = keyring.unwrap().current();
+    let path = path.unwrap();
+    let mut content = format!(
+        "key {} exported to {}",
+        keys::fingerprint(&key),
+        path.display()
+    );
+    let write_res = keys::wrap_key(&key, &passphrase)
+        .and_then(|wrapped| keys::write_key_file(&path, keys::to_hex(&wrapped).as_bytes()));
+    if let Err(error) = write_res {
+        content = format!("error exporting key!\n{}", error);
+    }
+    tokio::spawn(send_command_output(tx, output_message(content)));
+}
+
+fn import_key_file(
+    path: &PathBuf,
+    passphrase: &str,
+    grace: Option<String>,
+    keyring: &KeyRing,
+) -> Result<String, String> {
+    let grace = grace_period(grace)?;
+    let read_res = read_to_string(path);
+    if read_res.is_err() {
+        return Err(format!(
+            "error reading {}!\n{}",
+            path.display(),
+            read_res.err().unwrap()
+        ));
+    }
+    let wrapped = keys::from_hex(&read_res.unwrap())?;
+    let new_key = keys::unwrap_key(&wrapped, passphrase)?;
+    keyring.replace(new_key.clone(), grace)?;
+    Ok(format!(
+        "imported key {}, the old key is kept for {} hours",
+        keys::fingerprint(&new_key),
+        grace.as_secs() / 3600
+    ))
+}
+
+pub fn import_key(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut keyring = None;
+    let mut passphrase = String::new();
+    let mut path = PathBuf::new();
+    let mut grace = None;
+    for arg in args.unwrap() {
         match arg.name.as_str() {
-            "projects" => projects = arg.projects.unwrap().clone(),
+            "keyring" => keyring = arg.keyring,
+            "passphrase" => passphrase = arg.string.unwrap_or_default(),
+            "path" => path = PathBuf::from(arg.string.unwrap_or_default()),
+            "grace" => grace = arg.string,
             _ => {}
         }
     }
-    for mut project in projects {
-        if project.name == activate_target {
-            project.active = true;
-            result_string = String::from("Project Activated Successfully!");
-        } else {
-            project.active = false;
+    let keyring = keyring.unwrap();
+    let result = import_key_file(&path, &passphrase, grace, &keyring);
+    let content = match result {
+        Ok(success) => success,
+        Err(error) => format!("error importing key!\n{}", error),
+    };
+    tokio::spawn(send_command_output(tx, output_message(content)));
+}
+
+pub fn rotate_key(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut keyring = None;
+    let mut server = None;
+    let mut grace = None;
+    for arg in args.unwrap() {
+        match arg.name.as_str() {
+            "keyring" => keyring = arg.keyring,
+            "server" => server = arg.server,
+            "grace" => grace = arg.string,
+            _ => {}
         }
-        project.save_project();
     }
-    return result_string;
+    let keyring = keyring.unwrap();
+    let server = server.unwrap();
+    let grace_res = grace_period(grace);
+    if grace_res.is_err() {
+        tokio::spawn(send_command_output(
+            tx,
+            output_message(grace_res.err().unwrap()),
+        ));
+        return;
+    }
+    let grace = grace_res.unwrap();
+    // the server has to know the new key before we start using it, so nothing
+    // changes on disk until it says yes.
+    tokio::spawn(async move {
+        let new_key = crate::crytpo::generate_key();
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        let request = ServerRequest {
+            input: format!("rotate_key {}", keys::to_hex(&new_key)),
+            response_tx: Some(response_tx),
+        };
+        let content;
+        if server.send(request).await.is_err() {
+            content = String::from("error! the server connection is down, key not rotated");
+        } else {
+            match response_rx.await {
+                Ok(Ok(_)) => {
+                    let replace_res = keyring.replace(new_key.clone(), grace);
+                    if replace_res.is_err() {
+                        content = format!(
+                            "error saving the new key after the server accepted it!\n{}",
+                            replace_res.err().unwrap()
+                        );
+                    } else {
+                        content = format!(
+                            "rotated to key {}, the old key is kept for {} hours",
+                            keys::fingerprint(&new_key),
+                            grace.as_secs() / 3600
+                        );
+                    }
+                }
+                Ok(Err(error)) => {
+                    content = format!("error! server did not accept the new key\n{}", error);
+                }
+                Err(_) => {
+                    content = String::from("error! the server connection is down, key not rotated");
+                }
+            }
+        }
+        let _ = tx.send(output_message(content)).await;
+    });
 }
 
 pub fn initialize_interactive(
@@ -693,6 +1391,7 @@ pub fn prompt_interactive(
     let message = Message {
         source: Destination::Console,
         destination: Destination::Console,
+        payload: None,
         content: String::from(prompt),
     };
     tx.blocking_send(message);
@@ -712,7 +1411,173 @@ pub fn deinitialize_interactive(tx: Sender<Message>) {
     tx.blocking_send(Message {
         source: Destination::Control,
         destination: Destination::Console,
+        payload: None,
         content: String::from("noninteractive"),
     })
     .unwrap();
 }
+
+pub fn doctor(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut config = PathBuf::new();
+    let mut overrides = Overrides::default();
+    for arg in args.unwrap() {
+        if arg.name == "config" {
+            config = arg.path.unwrap();
+        } else if arg.name == "overrides" {
+            overrides = arg.overrides.unwrap();
+        }
+    }
+    // the checks shell out to distrobox and dial the server, keep that off
+    // the async workers.
+    tokio::spawn(async move {
+        let report_res =
+            tokio::task::spawn_blocking(move || doctor::report(&doctor::run(&config, &overrides)))
+                .await;
+        let content = match report_res {
+            Ok(report) => report,
+            Err(e) => format!("error running doctor!\n{}", e),
+        };
+        send_command_output(tx, output_message(content)).await;
+    });
+}
+
+pub fn upgrade_templates(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut config = PathBuf::new();
+    for arg in args.unwrap() {
+        if arg.name == "config" {
+            config = arg.path.unwrap();
+        }
+    }
+    let upgrade_res = templates::upgrade(&templates::templates_dir(&config));
+    let content = match upgrade_res {
+        Ok(report) => report.summary(),
+        Err(e) => format!("error upgrading note templates!\n{}", e),
+    };
+    tokio::spawn(send_command_output(tx, output_message(content)));
+}
+
+pub fn project_info(
+    args: Option<Vec<ToolArgument>>,
+    tx: Sender<Message>,
+    _command_tx: Option<Sender<Message>>,
+    _rx: Option<Receiver<Message>>,
+) {
+    let mut projects = Vec::new();
+    let mut name = None;
+    let mut changes = Vec::new();
+    for arg in args.unwrap() {
+        if arg.name == "projects" {
+            projects = arg.projects.unwrap();
+        } else if arg.name == "name" {
+            name = arg.string;
+        } else if PROJECT_INFO_KEYS.contains(&arg.name.as_str()) {
+            changes.push((arg.name, arg.string.unwrap_or_default()));
+        }
+    }
+    let project_res = find_project(projects, name);
+    if project_res.is_err() {
+        let content = format!("error! {}", project_res.err().unwrap());
+        tokio::spawn(send_command_output(tx, output_message(content)));
+        return;
+    }
+    let mut project = project_res.unwrap();
+    let mut output = String::new();
+    if !changes.is_empty() {
+        for (key, value) in &changes {
+            let set_res = project.set_info(key, value);
+            if set_res.is_err() {
+                let content = format!("error! {}", set_res.err().unwrap());
+                tokio::spawn(send_command_output(tx, output_message(content)));
+                return;
+            }
+        }
+        let save_res = project.save_project();
+        if save_res != "Success!" {
+            tokio::spawn(send_command_output(tx, output_message(save_res)));
+            return;
+        }
+        output.push_str("project updated!\n");
+    }
+    let mut lines = vec![String::from("field|value")];
+    lines.push(format!("name|{}", project.name));
+    lines.push(format!("stage|{}", project.stage.as_str()));
+    for key in PROJECT_INFO_KEYS {
+        lines.push(format!(
+            "{}|{}",
+            key,
+            project.get_info(key).unwrap_or_default()
+        ));
+    }
+    let mut table = Table::default();
+    table.build(lines);
+    output.push_str(&table.get_table());
+    tokio::spawn(send_command_output(tx, output_message(output)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn promote_by_name_reports_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = Project {
+            name: String::from("acme"),
+            stage: Stage::Current,
+            boxname: String::from("none"),
+            config: dir.path().join("acme.conf"),
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = channel(8);
+        let mut command = build_tools(tx)
+            .into_iter()
+            .find(|command| command.name == "promote_project")
+            .unwrap();
+        let mut args = vec![ToolArgument {
+            name: String::from("projects"),
+            projects: Some(vec![project]),
+            ..Default::default()
+        }];
+        for (name, value) in [("name", "acme"), ("templatebox", "template")] {
+            args.push(ToolArgument {
+                name: String::from(name),
+                string: Some(String::from(value)),
+                ..Default::default()
+            });
+        }
+        for name in ["current_files", "current_notes"] {
+            args.push(ToolArgument {
+                name: String::from(name),
+                path: Some(dir.path().join(name)),
+                ..Default::default()
+            });
+        }
+        command.args = Some(args);
+        // same as the cli does once name= is given.
+        command.interactive = false;
+
+        command
+            .execute(None, tokio::runtime::Handle::current())
+            .await;
+        let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            message
+                .content
+                .contains("only upcoming projects can be promoted")
+        );
+    }
+}
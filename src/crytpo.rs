@@ -1,25 +1,561 @@
 use chacha20poly1305::aead::generic_array::typenum::Unsigned;
-use chacha20poly1305::aead::generic_array::GenericArray;
-use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
-use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+use x25519_dalek::{PublicKey, ReusableSecret, StaticSecret};
+
+pub const KEY_LEN: usize = 32;
+pub const SEQUENCE_LEN: usize = 8;
+// how far behind the newest frame we still accept stragglers.
+pub const REPLAY_WINDOW: u64 = 64;
 
 pub fn generate_key() -> Vec<u8> {
     ChaCha20Poly1305::generate_key(&mut OsRng).to_vec()
 }
 
-pub fn encrypt(cleartext: &str, key: &[u8]) -> Vec<u8> {
-    let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
+pub fn generate_challenge() -> Vec<u8> {
+    let mut challenge = vec![0u8; 32];
+    OsRng.fill_bytes(&mut challenge);
+    challenge
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CryptoError {
+    WrongKeySize(usize),
+    BadLength(usize),
+    AuthFailure,
+    BadUtf8,
+    Replayed(u64),
+    TooOld { sequence: u64, newest: u64 },
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::WrongKeySize(len) => {
+                write!(f, "key must be {} bytes, got {}", KEY_LEN, len)
+            }
+            CryptoError::BadLength(len) => {
+                write!(f, "{} bytes is too short to be an encrypted frame", len)
+            }
+            CryptoError::AuthFailure => {
+                write!(
+                    f,
+                    "frame failed to authenticate, wrong key or tampered data"
+                )
+            }
+            CryptoError::BadUtf8 => write!(f, "decrypted data is not valid utf-8"),
+            CryptoError::Replayed(sequence) => write!(
+                f,
+                "rejected frame {}, it has already been received (replay?)",
+                sequence
+            ),
+            CryptoError::TooOld { sequence, newest } => write!(
+                f,
+                "rejected frame {}, it is older than the replay window (newest is {})",
+                sequence, newest
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+fn cipher(key: &[u8]) -> Result<ChaCha20Poly1305, CryptoError> {
+    ChaCha20Poly1305::new_from_slice(key).map_err(|_| CryptoError::WrongKeySize(key.len()))
+}
+
+// output is nonce || ciphertext. the aad isn't stored, the other side has to
+// already know it.
+pub fn encrypt_bytes(cleartext: &[u8], key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let cipher = cipher(key)?;
     let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
-    let mut obsf = cipher.encrypt(&nonce, cleartext.as_bytes()).unwrap();
+    let payload = Payload {
+        msg: cleartext,
+        aad,
+    };
+    let mut obsf = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| CryptoError::AuthFailure)?;
     obsf.splice(..0, nonce.iter().copied());
-    obsf
+    Ok(obsf)
+}
+
+// how long encrypt_bytes output is for a cleartext of the given length.
+pub fn sealed_len(cleartext_len: usize) -> usize {
+    type NonceSize = <ChaCha20Poly1305 as AeadCore>::NonceSize;
+    type TagSize = <ChaCha20Poly1305 as AeadCore>::TagSize;
+    NonceSize::to_usize() + cleartext_len + TagSize::to_usize()
 }
 
-pub fn decrypt(obsf: &[u8], key: &[u8]) -> String {
+pub fn decrypt_bytes(obsf: &[u8], key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
     type NonceSize = <ChaCha20Poly1305 as AeadCore>::NonceSize;
-    let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
+    type TagSize = <ChaCha20Poly1305 as AeadCore>::TagSize;
+    let cipher = cipher(key)?;
+    if obsf.len() < NonceSize::to_usize() + TagSize::to_usize() {
+        return Err(CryptoError::BadLength(obsf.len()));
+    }
     let (nonce, ciphertext) = obsf.split_at(NonceSize::to_usize());
-    let nonce = GenericArray::from_slice(nonce);
-    let plaintext = cipher.decrypt(nonce, ciphertext).unwrap();
-    String::from_utf8(plaintext).unwrap()
-}
\ No newline at end of file
+    let nonce: &Nonce = nonce.into();
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    cipher
+        .decrypt(nonce, payload)
+        .map_err(|_| CryptoError::AuthFailure)
+}
+
+pub fn encrypt(cleartext: &str, key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    encrypt_bytes(cleartext.as_bytes(), key, &[])
+}
+
+pub fn decrypt(obsf: &[u8], key: &[u8]) -> Result<String, CryptoError> {
+    let plaintext = decrypt_bytes(obsf, key, &[])?;
+    String::from_utf8(plaintext).map_err(|_| CryptoError::BadUtf8)
+}
+
+// keeps track of which sequence numbers we've already accepted. anything
+// older than the window, or seen before inside it, gets turned away.
+#[derive(Default, Clone)]
+pub struct ReplayWindow {
+    highest: u64,
+    seen: u64,
+    started: bool,
+}
+
+impl ReplayWindow {
+    pub fn check(&self, sequence: u64) -> Result<(), CryptoError> {
+        if !self.started || sequence > self.highest {
+            return Ok(());
+        }
+        let offset = self.highest - sequence;
+        if offset >= REPLAY_WINDOW {
+            return Err(CryptoError::TooOld {
+                sequence,
+                newest: self.highest,
+            });
+        }
+        if self.seen & (1 << offset) != 0 {
+            return Err(CryptoError::Replayed(sequence));
+        }
+        Ok(())
+    }
+
+    // only call this once the frame has authenticated, otherwise junk could
+    // push the window forward.
+    pub fn accept(&mut self, sequence: u64) {
+        if !self.started {
+            self.started = true;
+            self.highest = sequence;
+            self.seen = 1;
+        } else if sequence > self.highest {
+            let shift = sequence - self.highest;
+            if shift >= REPLAY_WINDOW {
+                self.seen = 1;
+            } else {
+                self.seen = (self.seen << shift) | 1;
+            }
+            self.highest = sequence;
+        } else {
+            self.seen |= 1 << (self.highest - sequence);
+        }
+    }
+}
+
+// same as encrypt but the frame carries its sequence number up front, and
+// that number is bound in as associated data so it can't be swapped out.
+pub fn encrypt_sequenced(
+    cleartext: &[u8],
+    key: &[u8],
+    sequence: u64,
+) -> Result<Vec<u8>, CryptoError> {
+    let aad = sequence.to_be_bytes();
+    let mut obsf = encrypt_bytes(cleartext, key, &aad)?;
+    obsf.splice(..0, aad.iter().copied());
+    Ok(obsf)
+}
+
+pub fn decrypt_sequenced(
+    obsf: &[u8],
+    key: &[u8],
+    window: &mut ReplayWindow,
+) -> Result<Vec<u8>, CryptoError> {
+    if obsf.len() < SEQUENCE_LEN {
+        return Err(CryptoError::BadLength(obsf.len()));
+    }
+    let (aad, sealed) = obsf.split_at(SEQUENCE_LEN);
+    let sequence = u64::from_be_bytes(aad.try_into().unwrap());
+    window.check(sequence)?;
+    let plaintext = decrypt_bytes(sealed, key, aad)?;
+    window.accept(sequence);
+    Ok(plaintext)
+}
+
+// a proof is the transcript sealed under the shared key. only someone holding
+// the key can make one that opens back up to the exact same transcript.
+pub fn prove(transcript: &str, key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    encrypt(transcript, key)
+}
+
+pub fn verify_proof(proof: &[u8], transcript: &str, key: &[u8]) -> bool {
+    decrypt(proof, key).is_ok_and(|opened| opened == transcript)
+}
+
+pub struct SessionKeys {
+    pub client_to_server: Vec<u8>,
+    pub server_to_client: Vec<u8>,
+}
+
+// a throwaway x25519 key pair for one connection. the client also uses it to
+// check the server's identity proof, so it can be used more than once, but
+// it's consumed when the session keys get derived so it never outlives the
+// handshake.
+pub fn generate_ephemeral() -> (ReusableSecret, Vec<u8>) {
+    let secret = ReusableSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    (secret, public.as_bytes().to_vec())
+}
+
+// an identity saved by an earlier run, so a server can keep the one its
+// clients already pinned.
+pub fn identity_from_secret(secret: &[u8]) -> Result<(StaticSecret, Vec<u8>), String> {
+    let bytes: [u8; 32] = match secret.try_into() {
+        Ok(bytes) => bytes,
+        Err(_) => return Err(format!("identity should be 32 bytes, not {}", secret.len())),
+    };
+    let secret = StaticSecret::from(bytes);
+    let public = PublicKey::from(&secret);
+    Ok((secret, public.as_bytes().to_vec()))
+}
+
+// runs the x25519 exchange and stretches the result into one key per
+// direction with hkdf. returns None if their public key is junk.
+pub fn derive_session_keys(
+    secret: ReusableSecret,
+    their_public: &[u8],
+    salt: &[u8],
+) -> Option<SessionKeys> {
+    let their_public: [u8; 32] = their_public.try_into().ok()?;
+    let shared = secret.diffie_hellman(&PublicKey::from(their_public));
+    if !shared.was_contributory() {
+        return None;
+    }
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), shared.as_bytes());
+    let mut client_to_server = vec![0u8; KEY_LEN];
+    let mut server_to_client = vec![0u8; KEY_LEN];
+    hkdf.expand(b"tetanus session client->server", &mut client_to_server)
+        .ok()?;
+    hkdf.expand(b"tetanus session server->client", &mut server_to_client)
+        .ok()?;
+    Some(SessionKeys {
+        client_to_server,
+        server_to_client,
+    })
+}
+
+// the server's long term x25519 key. clients pin the public half and the
+// server proves it holds the secret half on every handshake.
+pub fn generate_identity() -> (StaticSecret, Vec<u8>) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    (secret, public.as_bytes().to_vec())
+}
+
+// both ends can work this out, the server from its identity secret and the
+// client's ephemeral public key, the client the other way round. nobody else
+// can, so a transcript sealed under it proves who the server is.
+fn identity_proof_key(shared: &[u8], identity_public: &[u8], client_public: &[u8]) -> Vec<u8> {
+    let salt = [identity_public, client_public].concat();
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared);
+    let mut key = vec![0u8; KEY_LEN];
+    hkdf.expand(b"tetanus identity proof", &mut key).unwrap();
+    key
+}
+
+pub fn prove_identity(
+    identity: &StaticSecret,
+    client_public: &[u8],
+    transcript: &str,
+) -> Result<Vec<u8>, CryptoError> {
+    let client_public: [u8; 32] = client_public
+        .try_into()
+        .map_err(|_| CryptoError::BadLength(client_public.len()))?;
+    let client_public = PublicKey::from(client_public);
+    let shared = identity.diffie_hellman(&client_public);
+    if !shared.was_contributory() {
+        return Err(CryptoError::AuthFailure);
+    }
+    let identity_public = PublicKey::from(identity);
+    let key = identity_proof_key(
+        shared.as_bytes(),
+        identity_public.as_bytes(),
+        client_public.as_bytes(),
+    );
+    prove(transcript, &key)
+}
+
+pub fn verify_identity(
+    secret: &ReusableSecret,
+    identity_public: &[u8],
+    proof: &[u8],
+    transcript: &str,
+) -> bool {
+    let identity_res: Result<[u8; 32], _> = identity_public.try_into();
+    if identity_res.is_err() {
+        return false;
+    }
+    let shared = secret.diffie_hellman(&PublicKey::from(identity_res.unwrap()));
+    if !shared.was_contributory() {
+        return false;
+    }
+    let client_public = PublicKey::from(secret);
+    let key = identity_proof_key(shared.as_bytes(), identity_public, client_public.as_bytes());
+    verify_proof(proof, transcript, &key)
+}
+
+// the static key (or enrollment token) covers the handshake in both
+// directions, so split it into one key per direction first. that way a frame
+// the client sent can't be bounced back at it as if the server had sent it.
+pub fn derive_handshake_keys(key: &[u8]) -> SessionKeys {
+    let hkdf = Hkdf::<Sha256>::new(Some(b"tetanus handshake"), key);
+    let mut client_to_server = vec![0u8; KEY_LEN];
+    let mut server_to_client = vec![0u8; KEY_LEN];
+    hkdf.expand(b"tetanus handshake client->server", &mut client_to_server)
+        .unwrap();
+    hkdf.expand(b"tetanus handshake server->client", &mut server_to_client)
+        .unwrap();
+    SessionKeys {
+        client_to_server,
+        server_to_client,
+    }
+}
+
+// enrollment happens before we share a key with the server, so the one-time
+// token stands in for it until the exchange is done.
+pub fn derive_enrollment_key(token: &str) -> Vec<u8> {
+    let hkdf = Hkdf::<Sha256>::new(Some(b"tetanus enrollment"), token.trim().as_bytes());
+    let mut key = vec![0u8; KEY_LEN];
+    hkdf.expand(b"tetanus enrollment key", &mut key).unwrap();
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = generate_key();
+        let sealed = encrypt_bytes(b"\x00binary\xff", &key, b"aad").unwrap();
+        assert_eq!(sealed.len(), sealed_len(8));
+        assert_eq!(
+            decrypt_bytes(&sealed, &key, b"aad").unwrap(),
+            b"\x00binary\xff"
+        );
+        let sealed = encrypt("hello", &key).unwrap();
+        assert_eq!(decrypt(&sealed, &key).unwrap(), "hello");
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails_to_authenticate() {
+        let key = generate_key();
+        let sealed = encrypt_bytes(b"hello", &key, &[]).unwrap();
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 0x80;
+            assert_eq!(
+                decrypt_bytes(&tampered, &key, &[]),
+                Err(CryptoError::AuthFailure)
+            );
+        }
+        assert_eq!(
+            decrypt_bytes(&sealed, &generate_key(), &[]),
+            Err(CryptoError::AuthFailure)
+        );
+    }
+
+    #[test]
+    fn wrong_aad_fails_to_authenticate() {
+        let key = generate_key();
+        let sealed = encrypt_bytes(b"hello", &key, b"client->server").unwrap();
+        assert_eq!(
+            decrypt_bytes(&sealed, &key, b"server->client"),
+            Err(CryptoError::AuthFailure)
+        );
+        assert_eq!(
+            decrypt_bytes(&sealed, &key, &[]),
+            Err(CryptoError::AuthFailure)
+        );
+    }
+
+    #[test]
+    fn short_input_is_an_error() {
+        let key = generate_key();
+        for len in 0..sealed_len(0) {
+            assert_eq!(
+                decrypt_bytes(&vec![0u8; len], &key, &[]),
+                Err(CryptoError::BadLength(len))
+            );
+        }
+        let mut window = ReplayWindow::default();
+        for len in 0..SEQUENCE_LEN {
+            assert_eq!(
+                decrypt_sequenced(&vec![0u8; len], &key, &mut window),
+                Err(CryptoError::BadLength(len))
+            );
+        }
+        assert_eq!(
+            decrypt_sequenced(&[0u8; SEQUENCE_LEN], &key, &mut window),
+            Err(CryptoError::BadLength(0))
+        );
+    }
+
+    #[test]
+    fn wrong_key_size_is_an_error() {
+        assert_eq!(
+            encrypt("hello", &[0u8; 16]),
+            Err(CryptoError::WrongKeySize(16))
+        );
+        assert_eq!(
+            decrypt_bytes(&[0u8; 64], &[0u8; 33], &[]),
+            Err(CryptoError::WrongKeySize(33))
+        );
+    }
+
+    fn accepted(window: &mut ReplayWindow, sequence: u64) -> Result<(), CryptoError> {
+        window.check(sequence)?;
+        window.accept(sequence);
+        Ok(())
+    }
+
+    #[test]
+    fn replay_window_rejects_duplicates() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(accepted(&mut window, 0), Ok(()));
+        assert_eq!(accepted(&mut window, 0), Err(CryptoError::Replayed(0)));
+        assert_eq!(accepted(&mut window, 2), Ok(()));
+        // stragglers inside the window are fine once, then not again.
+        assert_eq!(accepted(&mut window, 1), Ok(()));
+        assert_eq!(accepted(&mut window, 1), Err(CryptoError::Replayed(1)));
+        assert_eq!(accepted(&mut window, 2), Err(CryptoError::Replayed(2)));
+    }
+
+    #[test]
+    fn replay_window_edges() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(accepted(&mut window, 100), Ok(()));
+        // 63 behind is the oldest slot still in the window.
+        assert_eq!(accepted(&mut window, 100 - (REPLAY_WINDOW - 1)), Ok(()));
+        assert_eq!(
+            accepted(&mut window, 100 - (REPLAY_WINDOW - 1)),
+            Err(CryptoError::Replayed(37))
+        );
+        assert_eq!(
+            accepted(&mut window, 100 - REPLAY_WINDOW),
+            Err(CryptoError::TooOld {
+                sequence: 36,
+                newest: 100
+            })
+        );
+
+        // moving up by 63 keeps 100 in the last slot.
+        assert_eq!(accepted(&mut window, 163), Ok(()));
+        assert_eq!(accepted(&mut window, 100), Err(CryptoError::Replayed(100)));
+        assert_eq!(accepted(&mut window, 101), Ok(()));
+        // one more and it falls off the end.
+        assert_eq!(accepted(&mut window, 164), Ok(()));
+        assert_eq!(
+            accepted(&mut window, 100),
+            Err(CryptoError::TooOld {
+                sequence: 100,
+                newest: 164
+            })
+        );
+    }
+
+    #[test]
+    fn replay_window_jump_clears_old_bits() {
+        let mut window = ReplayWindow::default();
+        for sequence in 0..REPLAY_WINDOW {
+            assert_eq!(accepted(&mut window, sequence), Ok(()));
+        }
+        // a jump of exactly a window leaves nothing behind but the new frame.
+        let newest = 2 * REPLAY_WINDOW - 1;
+        assert_eq!(accepted(&mut window, newest), Ok(()));
+        for sequence in REPLAY_WINDOW..newest {
+            assert_eq!(window.check(sequence), Ok(()));
+        }
+        assert_eq!(window.check(newest), Err(CryptoError::Replayed(newest)));
+        assert!(matches!(
+            window.check(REPLAY_WINDOW - 1),
+            Err(CryptoError::TooOld { .. })
+        ));
+    }
+
+    #[test]
+    fn sequenced_frames_are_bound_to_their_number() {
+        let key = generate_key();
+        let mut window = ReplayWindow::default();
+        let sealed = encrypt_sequenced(b"hello", &key, 7).unwrap();
+        assert_eq!(
+            decrypt_sequenced(&sealed, &key, &mut window).unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            decrypt_sequenced(&sealed, &key, &mut window),
+            Err(CryptoError::Replayed(7))
+        );
+        // swapping the number out breaks the tag and doesn't move the window.
+        let mut renumbered = sealed.clone();
+        renumbered[..SEQUENCE_LEN].copy_from_slice(&8u64.to_be_bytes());
+        assert_eq!(
+            decrypt_sequenced(&renumbered, &key, &mut window),
+            Err(CryptoError::AuthFailure)
+        );
+        assert_eq!(window.check(8), Ok(()));
+    }
+
+    #[test]
+    fn identity_proof_only_verifies_for_the_holder() {
+        let (identity, identity_public) = generate_identity();
+        let (impostor, impostor_public) = generate_identity();
+        let (secret, client_public) = generate_ephemeral();
+        let proof = prove_identity(&identity, &client_public, "transcript").unwrap();
+        assert!(verify_identity(
+            &secret,
+            &identity_public,
+            &proof,
+            "transcript"
+        ));
+        assert!(!verify_identity(&secret, &identity_public, &proof, "other"));
+        assert!(!verify_identity(
+            &secret,
+            &impostor_public,
+            &proof,
+            "transcript"
+        ));
+        let forged = prove_identity(&impostor, &client_public, "transcript").unwrap();
+        assert!(!verify_identity(
+            &secret,
+            &identity_public,
+            &forged,
+            "transcript"
+        ));
+        assert!(!verify_identity(&secret, &[0u8; 31], &proof, "transcript"));
+        assert_eq!(
+            prove_identity(&identity, &[0u8; 31], "transcript"),
+            Err(CryptoError::BadLength(31))
+        );
+    }
+
+    #[test]
+    fn bad_utf8_is_an_error() {
+        let key = generate_key();
+        let sealed = encrypt_bytes(&[0xff, 0xfe], &key, &[]).unwrap();
+        assert_eq!(decrypt(&sealed, &key), Err(CryptoError::BadUtf8));
+    }
+}
@@ -0,0 +1,366 @@
+use colored::Colorize;
+use std::fs::read_dir;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::keys;
+use crate::settings::{Overrides, Settings};
+use crate::table::Table;
+use crate::templates::{self, TEMPLATE_NAMES};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: String) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail,
+        }
+    }
+}
+
+fn check_dir(name: &str, path: &Option<PathBuf>) -> Check {
+    if path.is_none() {
+        return Check::new(name, Status::Warn, String::from("not set"));
+    }
+    let path = path.as_ref().unwrap();
+    if !path.exists() {
+        return Check::new(
+            name,
+            Status::Fail,
+            format!("{} doesn't exist", path.display()),
+        );
+    }
+    if !path.is_dir() {
+        return Check::new(
+            name,
+            Status::Fail,
+            format!("{} isn't a directory", path.display()),
+        );
+    }
+    Check::new(name, Status::Pass, path.display().to_string())
+}
+
+fn check_projects(config_dir: &Path) -> Check {
+    let projects = config_dir.join("projects");
+    let dir_res = read_dir(&projects);
+    if dir_res.is_err() {
+        return Check::new(
+            "projects dir",
+            Status::Fail,
+            format!(
+                "can't read {}: {}",
+                projects.display(),
+                dir_res.err().unwrap()
+            ),
+        );
+    }
+    let count = dir_res
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".conf"))
+        .count();
+    if count == 0 {
+        return Check::new(
+            "projects dir",
+            Status::Warn,
+            format!("{} has no projects in it", projects.display()),
+        );
+    }
+    Check::new(
+        "projects dir",
+        Status::Pass,
+        format!("{} projects in {}", count, projects.display()),
+    )
+}
+
+fn check_templates(config_dir: &Path) -> Check {
+    let templates = config_dir.join("note_templates");
+    if !templates.is_dir() {
+        return Check::new(
+            "note_templates",
+            Status::Fail,
+            format!("{} doesn't exist", templates.display()),
+        );
+    }
+    if !templates::is_installed(&templates) {
+        return Check::new(
+            "note_templates",
+            Status::Warn,
+            String::from("bundled templates were never installed, run upgrade_templates"),
+        );
+    }
+    let mut missing = Vec::new();
+    for name in TEMPLATE_NAMES {
+        let mut template = templates.clone();
+        template.push(name);
+        if !template.is_dir() {
+            missing.push(name);
+        }
+    }
+    if !missing.is_empty() {
+        return Check::new(
+            "note_templates",
+            Status::Warn,
+            format!(
+                "missing {}, run upgrade_templates to put them back",
+                missing.join(", ")
+            ),
+        );
+    }
+    Check::new(
+        "note_templates",
+        Status::Pass,
+        templates.display().to_string(),
+    )
+}
+
+fn check_key(settings: &Settings) -> Check {
+    let path = &settings.key_file;
+    if !path.exists() {
+        return Check::new(
+            "key file",
+            Status::Fail,
+            format!("{} doesn't exist", path.display()),
+        );
+    }
+    let permission_res = keys::check_permissions(path);
+    if permission_res.is_err() {
+        return Check::new("key file", Status::Fail, permission_res.err().unwrap());
+    }
+    let inspect_res = keys::inspect_key_file(path);
+    if inspect_res.is_err() {
+        return Check::new("key file", Status::Fail, inspect_res.err().unwrap());
+    }
+    let mode = path
+        .metadata()
+        .map(|meta| meta.permissions().mode() & 0o777)
+        .unwrap_or(0);
+    if mode & 0o040 != 0 {
+        return Check::new(
+            "key file",
+            Status::Warn,
+            format!("group readable (mode {:o}), chmod 600 it", mode),
+        );
+    }
+    if inspect_res.unwrap() {
+        return Check::new(
+            "key file",
+            Status::Pass,
+            String::from("32 byte key, passphrase protected"),
+        );
+    }
+    Check::new(
+        "key file",
+        Status::Pass,
+        String::from("32 byte key, no passphrase"),
+    )
+}
+
+fn check_distrobox(settings: &Settings) -> Vec<Check> {
+    if !settings.distrobox {
+        return vec![Check::new(
+            "distrobox",
+            Status::Pass,
+            String::from("turned off in the config"),
+        )];
+    }
+    let version_res = Command::new("distrobox").arg("version").output();
+    if version_res.is_err() || !version_res.as_ref().unwrap().status.success() {
+        return vec![
+            Check::new(
+                "distrobox",
+                Status::Fail,
+                String::from("distrobox isn't installed or isn't on the PATH"),
+            ),
+            Check::new(
+                "template box",
+                Status::Fail,
+                String::from("can't check without distrobox"),
+            ),
+        ];
+    }
+    let version = String::from_utf8_lossy(&version_res.unwrap().stdout)
+        .trim()
+        .to_string();
+    let distrobox = Check::new("distrobox", Status::Pass, version);
+    if settings.templatebox.is_none() {
+        return vec![
+            distrobox,
+            Check::new(
+                "template box",
+                Status::Fail,
+                String::from("templatebox isn't set"),
+            ),
+        ];
+    }
+    let templatebox = settings.templatebox.clone().unwrap();
+    let list_res = Command::new("distrobox").arg("list").output();
+    if list_res.is_err() {
+        return vec![
+            distrobox,
+            Check::new(
+                "template box",
+                Status::Fail,
+                format!("error listing boxes: {}", list_res.err().unwrap()),
+            ),
+        ];
+    }
+    let listing = String::from_utf8_lossy(&list_res.unwrap().stdout).to_string();
+    let found = listing.lines().skip(1).any(|line| {
+        line.split('|')
+            .nth(1)
+            .is_some_and(|name| name.trim() == templatebox)
+    });
+    if !found {
+        return vec![
+            distrobox,
+            Check::new(
+                "template box",
+                Status::Fail,
+                format!("no distrobox named {}", templatebox),
+            ),
+        ];
+    }
+    vec![
+        distrobox,
+        Check::new("template box", Status::Pass, templatebox),
+    ]
+}
+
+fn check_server(address: &str, config: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    match address.to_socket_addrs() {
+        Err(error) => checks.push(Check::new(
+            "server",
+            Status::Fail,
+            format!("can't resolve {}: {}", address, error),
+        )),
+        Ok(mut addrs) => {
+            if addrs.any(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).is_ok()) {
+                checks.push(Check::new(
+                    "server",
+                    Status::Pass,
+                    format!("{} is up", address),
+                ));
+            } else {
+                checks.push(Check::new(
+                    "server",
+                    Status::Fail,
+                    format!("nothing answering on {}", address),
+                ));
+            }
+        }
+    }
+    match keys::load_server_identity(config) {
+        Err(error) => checks.push(Check::new("server identity", Status::Fail, error)),
+        Ok(None) => checks.push(Check::new(
+            "server identity",
+            Status::Warn,
+            String::from("not pinned, enroll with --enroll to pin it"),
+        )),
+        Ok(Some(identity)) => checks.push(Check::new(
+            "server identity",
+            Status::Pass,
+            keys::fingerprint(&identity),
+        )),
+    }
+    checks
+}
+
+// runs every check it can. a broken config still gets reported on, the
+// checks that need it just get skipped.
+pub fn run(config: &Path, overrides: &Overrides) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let settings_res = Settings::load_with(config, overrides);
+    if settings_res.is_err() {
+        checks.push(Check::new(
+            "config file",
+            Status::Fail,
+            settings_res.err().unwrap(),
+        ));
+        return checks;
+    }
+    let settings = settings_res.unwrap();
+    checks.push(Check::new(
+        "config file",
+        Status::Pass,
+        config.display().to_string(),
+    ));
+    let config_dir = config.parent().unwrap_or(config);
+    checks.push(check_dir("current_files", &settings.current_files));
+    checks.push(check_dir("current_notes", &settings.current_notes));
+    checks.push(check_dir("upcoming_files", &settings.upcoming_files));
+    checks.push(check_dir("upcoming_notes", &settings.upcoming_notes));
+    checks.push(check_dir("archive", &settings.archive));
+    checks.push(check_dir("tools", &settings.tools));
+    checks.push(check_templates(config_dir));
+    checks.push(check_projects(config_dir));
+    checks.push(check_key(&settings));
+    checks.append(&mut check_distrobox(&settings));
+    checks.append(&mut check_server(&settings.server_address, config));
+    checks
+}
+
+pub fn failed(checks: &[Check]) -> bool {
+    checks.iter().any(|check| check.status == Status::Fail)
+}
+
+pub fn report(checks: &Vec<Check>) -> String {
+    let mut lines = vec![String::from("check|status|detail")];
+    for check in checks {
+        // the table splits on | and can't handle multi line cells.
+        let detail = check.detail.replace('|', "/").replace('\n', " ");
+        lines.push(format!(
+            "{}|{}|{}",
+            check.name,
+            check.status.as_str(),
+            detail
+        ));
+    }
+    let mut table = Table::default();
+    table.build(lines);
+    let count = |status: Status| checks.iter().filter(|c| c.status == status).count();
+    let summary = format!(
+        "{} passed, {} warnings, {} failed",
+        count(Status::Pass),
+        count(Status::Warn),
+        count(Status::Fail)
+    );
+    let summary = if count(Status::Fail) > 0 {
+        summary.red().to_string()
+    } else if count(Status::Warn) > 0 {
+        summary.yellow().to_string()
+    } else {
+        summary.green().to_string()
+    };
+    format!("{}\n{}", table.get_table(), summary)
+}
@@ -1,15 +1,238 @@
 use std::collections::HashMap;
-use std::fs::{File, create_dir_all, read_to_string, remove_file};
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::{create_dir_all, read_to_string, remove_file};
+use std::path::{Path, PathBuf};
 
 use crate::get_user_input;
+use crate::project::{Project, Stage};
 use crate::print_success;
-use crate::{crytpo, print_error};
+use crate::settings::{self, Settings};
+use crate::{crytpo, keys, print_error, templates};
 
-pub fn install(config: &PathBuf) -> bool {
+// answers that aren't settings themselves but steer the installer.
+const EXTRA_ANSWERS: [&str; 5] = [
+    "overwrite",
+    "use_defaults",
+    "new_key",
+    "protect_key",
+    "key_passphrase",
+];
+
+#[derive(Debug, Default, clap::Args)]
+pub struct InstallArgs {
+    #[arg(
+        long,
+        help = "toml file of answers for --install, takes any setting plus overwrite, use_defaults, new_key, protect_key and key_passphrase"
+    )]
+    pub answers: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "don't ask anything during --install, take the defaults and fail if something required is missing"
+    )]
+    pub yes: bool,
+
+    #[arg(long, help = "install: where to keep the client key")]
+    pub key_file: Option<String>,
+
+    #[arg(long, help = "install: use distrobox for attack environments (yes/no)")]
+    pub distrobox: Option<String>,
+
+    #[arg(
+        long,
+        help = "install: the template distrobox new projects are cloned from"
+    )]
+    pub templatebox: Option<String>,
+
+    #[arg(long, help = "install: where current project files are stored")]
+    pub current_files: Option<String>,
+
+    #[arg(long, help = "install: where current project notes are stored")]
+    pub current_notes: Option<String>,
+
+    #[arg(long, help = "install: where upcoming project files are stored")]
+    pub upcoming_files: Option<String>,
+
+    #[arg(long, help = "install: where upcoming project notes are stored")]
+    pub upcoming_notes: Option<String>,
+
+    #[arg(long, help = "install: where archived projects are kept")]
+    pub archive: Option<String>,
+
+    #[arg(long, help = "install: where your custom tools live")]
+    pub tools: Option<String>,
+
+    #[arg(
+        long,
+        help = "install: command used to launch your terminal, like konsole -e"
+    )]
+    pub terminal: Option<String>,
+}
+
+// where the installer gets its answers from. anything given up front wins,
+// then we either ask or, when running unattended, fall back to the default
+// and bail if there isn't one.
+pub struct Answers {
+    values: HashMap<String, String>,
+    interactive: bool,
+}
+
+impl Answers {
+    pub fn interactive() -> Self {
+        Self {
+            values: HashMap::new(),
+            interactive: true,
+        }
+    }
+
+    // answers file first, then flags on top of it.
+    pub fn from_args(args: &InstallArgs, server: Option<String>) -> Result<Self, String> {
+        let mut answers = Answers::interactive();
+        answers.interactive = !args.yes && args.answers.is_none();
+        if let Some(path) = &args.answers {
+            answers.values = read_answers_file(path)?;
+        }
+        let flags = [
+            ("server_address", &server),
+            ("key_file", &args.key_file),
+            ("distrobox", &args.distrobox),
+            ("templatebox", &args.templatebox),
+            ("current_files", &args.current_files),
+            ("current_notes", &args.current_notes),
+            ("upcoming_files", &args.upcoming_files),
+            ("upcoming_notes", &args.upcoming_notes),
+            ("archive", &args.archive),
+            ("tools", &args.tools),
+            ("terminal", &args.terminal),
+        ];
+        for (key, value) in flags {
+            if value.is_some() {
+                answers
+                    .values
+                    .insert(key.to_string(), value.clone().unwrap());
+            }
+        }
+        Ok(answers)
+    }
+
+    fn missing(&self, key: &str) -> String {
+        let flag = match key {
+            "server_address" => String::from("--server"),
+            _ if settings::KEYS.contains(&key) => format!("--{}", key.replace('_', "-")),
+            _ => String::from("the answers file"),
+        };
+        format!(
+            "no answer for {}, pass it with {} or add {} to the answers file",
+            key, flag, key
+        )
+    }
+
+    pub fn confirm(&self, key: &str, prompt: &str, default: bool) -> Result<bool, String> {
+        if self.values.contains_key(key) {
+            return settings::parse_bool(&self.values[key]);
+        }
+        if !self.interactive {
+            return Ok(default);
+        }
+        Ok(get_user_input(prompt).to_lowercase().contains("y"))
+    }
+
+    pub fn value(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+
+    // sets a setting from the answers, asking until it's valid when
+    // interactive.
+    pub fn setting(
+        &self,
+        settings: &mut Settings,
+        key: &str,
+        prompt: &str,
+        required: bool,
+    ) -> Result<(), String> {
+        if self.values.contains_key(key) {
+            return settings.set(key, &self.values[key]);
+        }
+        if !self.interactive {
+            if required && settings.get(key).is_none() {
+                return Err(self.missing(key));
+            }
+            return Ok(());
+        }
+        loop {
+            let set_res = settings.set(key, &get_user_input(prompt));
+            if set_res.is_ok() {
+                return Ok(());
+            }
+            print_error("that won't work...", set_res.err());
+        }
+    }
+
+    // settings that come with a default, only asked when the user turned
+    // the defaults down.
+    pub fn defaulted_setting(
+        &self,
+        settings: &mut Settings,
+        key: &str,
+        prompt: &str,
+        ask: bool,
+    ) -> Result<(), String> {
+        if !ask && !self.values.contains_key(key) {
+            return Ok(());
+        }
+        self.setting(settings, key, prompt, false)
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+}
+
+fn read_answers_file(path: &PathBuf) -> Result<HashMap<String, String>, String> {
+    let read_res = read_to_string(path);
+    if read_res.is_err() {
+        return Err(format!(
+            "error reading answers file {}!\n{}",
+            path.display(),
+            read_res.err().unwrap()
+        ));
+    }
+    let parse_res = read_res.unwrap().parse::<toml::Table>();
+    if parse_res.is_err() {
+        return Err(format!(
+            "error parsing answers file!\n{}",
+            parse_res.err().unwrap()
+        ));
+    }
+    let mut values = HashMap::new();
+    for (key, value) in parse_res.unwrap() {
+        if !settings::KEYS.contains(&key.as_str()) && !EXTRA_ANSWERS.contains(&key.as_str()) {
+            return Err(format!("unknown answer {} in the answers file", key));
+        }
+        let value = match value {
+            toml::Value::String(text) => text,
+            toml::Value::Boolean(true) => String::from("yes"),
+            toml::Value::Boolean(false) => String::from("no"),
+            toml::Value::Integer(number) => number.to_string(),
+            _ => return Err(format!("{} in the answers file needs to be a string", key)),
+        };
+        values.insert(key, value);
+    }
+    Ok(values)
+}
+
+pub fn install(config: &Path, answers: &Answers) -> bool {
+    let install_res = run_install(config, answers);
+    if install_res.is_err() {
+        print_error("error installing!", install_res.err());
+        return false;
+    }
+    install_res.unwrap()
+}
+
+fn run_install(config: &Path, answers: &Answers) -> Result<bool, String> {
     let mut new = true;
-    let mut config_folder = config.clone();
+    let mut config_folder = config.to_path_buf();
     config_folder.pop();
     let mut config_file = config_folder.clone();
     config_file.push("config.conf");
@@ -17,7 +240,17 @@ pub fn install(config: &PathBuf) -> bool {
     let mut note_templates_path = config_folder.clone();
     project_config.push("projects");
     note_templates_path.push("note_templates");
-    let mut settings = HashMap::new();
+    let mut settings = Settings::for_config(config);
+    // nobody is there to type the passphrase in, catch it before anything
+    // gets written.
+    if !answers.is_interactive()
+        && answers.value("key_passphrase").is_none()
+        && answers.confirm("protect_key", "", false)?
+    {
+        return Err(String::from(
+            "protect_key needs key_passphrase when running unattended",
+        ));
+    }
     if !config_folder.exists() {
         let dir_create_res = create_dir_all(&config_folder);
         if dir_create_res.is_err() {
@@ -25,66 +258,47 @@ pub fn install(config: &PathBuf) -> bool {
                 "error creating configuration directory!",
                 Some(dir_create_res.err().unwrap().to_string()),
             );
-            return false;
+            return Ok(false);
         }
         print_success("configuration folder created successfully!");
     } else {
         print_success("configuration folder already exists!");
     }
     if config_file.exists() {
-        if get_user_input("the config file already exists, would you like to delete it and create a full new one?").to_lowercase().contains("y"){
+        if answers.confirm("overwrite", "the config file already exists, would you like to delete it and create a full new one?", false)?{
             let remove_res = remove_file(&config_file);
             if remove_res.is_err(){
                 print_error("error removing file, please manually delete and try again...", Some(remove_res.err().unwrap().to_string()));
-                return false;
-            }
-            if project_config.exists(){
-                let remove_res = remove_file(&project_config);
-                if remove_res.is_err(){
-                    print_error("error removing projects file", Some(remove_res.err().unwrap().to_string()));
-                    return false;
-                }
+                return Ok(false);
             }
         }
         else{
             new = false;
-            let config_read_res = read_to_string(&config_file);
-            if config_read_res.is_err(){
-                print_error("error reading config file!", Some(config_read_res.err().unwrap().to_string()));
-                return false;
-            }
-            let config_string = config_read_res.unwrap();
-            for line in config_string.lines(){
-                if line.contains(":"){
-                    let line_vec: Vec<&str> = line.split(":").collect();
-                    if settings.contains_key(line_vec[0]){
-                        settings.remove(line_vec[0]);
-                        settings.insert(line_vec[0].to_string(), line_vec[1].to_string());
-                    }
-                    else{
-                        settings.insert(line_vec[0].to_string(), line_vec[1].to_string());
-                    }
-                }
-            }
+            settings = Settings::load(&config_file)?;
             print_success("read existing config file successfully!");
-            println!("entering edit loop...");
-            loop{
-                println!("{}", config_string);
-                let setting = get_user_input("which setting would you like to change? (ENTER DONE IN ALL CAPS WHEN YOU'RE FINISHED");
-                let value = get_user_input("what would you like to change it to?");
-                if setting.contains("DONE"){
-                    break;
+            for key in settings::KEYS {
+                if answers.value(key).is_some() {
+                    settings.set(key, &answers.value(key).unwrap())?;
                 }
-                else {
-                    if settings.contains_key(&setting){
-                        settings.remove(&setting);
-                        settings.insert(setting, value);
+            }
+            if answers.is_interactive() {
+                println!("entering edit loop...");
+                loop{
+                    for key in settings::KEYS{
+                        println!("{}|{}", key, settings.get(key).unwrap_or_default());
                     }
-                    else{
-                        settings.insert(setting, value);
+                    let setting = get_user_input("which setting would you like to change? (ENTER DONE IN ALL CAPS WHEN YOU'RE FINISHED");
+                    if setting.contains("DONE"){
+                        break;
+                    }
+                    let value = get_user_input("what would you like to change it to?");
+                    let set_res = settings.set(&setting, &value);
+                    if set_res.is_err(){
+                        print_error("error changing setting!", set_res.err());
                     }
                 }
             }
+            settings.save(&config_file)?;
         }
     }
     if !project_config.exists() {
@@ -105,132 +319,300 @@ pub fn install(config: &PathBuf) -> bool {
             );
         }
     }
+    match templates::upgrade(&note_templates_path) {
+        Err(error) => print_error("error installing note templates!", Some(error)),
+        Ok(report) => print_success(&report.summary()),
+    }
     if new {
-        println!("server_address|127.0.0.1:31337");
-        println!("key_file|{}/key", &config_folder.display());
-        println!("distrobox|yes");
-        if !get_user_input("are these defaults ok?")
-            .to_lowercase()
-            .contains("y")
-        {
-            let server_address = get_user_input("what is your server address then?");
-            let key_file = get_user_input("what is your key file then?");
-            if get_user_input("will you be using distrobox for your attack environments?")
-                .to_lowercase()
-                .contains("y")
-            {
-                settings.insert("distrobox".to_string(), "yes".to_string());
-                settings.insert(
-                    "templatebox".to_string(),
-                    get_user_input("name of your tempalte distrobox?"),
-                );
-            } else {
-                settings.insert("distrobox".to_string(), "no".to_string());
-            }
-            settings.insert("server_address".to_string(), server_address);
-            settings.insert("key_file".to_string(), key_file);
-        } else {
-            settings.insert("server_address".to_string(), "127.0.0.1:31337".to_string());
-            settings.insert(
-                "key_file".to_string(),
-                format!("{}/key", config_folder.display()),
-            );
-            settings.insert("distrobox".to_string(), "yes".to_string());
-            settings.insert(
-                "templatebox".to_string(),
-                get_user_input("name of the distrobox you will use?"),
-            );
+        if answers.is_interactive() {
+            println!("server_address|{}", settings.server_address);
+            println!("key_file|{}", settings.key_file.display());
+            println!("distrobox|yes");
         }
-        settings.insert("current_files".to_string(), get_user_input("full path to where you want your current project's files stored? example: /home/pyro/projects/current"));
-        settings.insert("current_notes".to_string(), get_user_input("full path to where you want your current project's notes stored example: /home/pyro/notes/current"));
-        settings.insert("upcoming_files".to_string(),get_user_input("full path to where you want your upcoming project's files stored example: /home/pyro/projects/upcoming"));
-        settings.insert("upcoming_notes".to_string(), get_user_input("full path to where you want your upcoming project's notes stored exmple: /home/pyro/notes/upcoming"));
-        settings.insert(
-            "tools".to_string(),
-            get_user_input(
-                "full path to where you store your custom tools (like those from github)?",
-            ),
-        );
-        settings.insert("terminal".to_string(), get_user_input("command used to launch your terminal while executing a command (for exmaple konsole in kde is konsole -e)?"));
-        print_success("sweet, we have all we need, writing config file...");
-        let out_file_res = File::create_new(&config_file);
-        if out_file_res.is_err() {
-            print_error(
-                "error creating config file!",
-                Some(out_file_res.err().unwrap().to_string()),
-            );
-            return false;
-        }
-        let mut out_file = out_file_res.unwrap();
-        for setting in settings.keys() {
-            let outline = format!("{}|{}\n", setting, settings[setting]);
-            let write_res = out_file.write(outline.as_bytes());
-            if write_res.is_err() {
-                print_error(
-                    "error writing to config file",
-                    Some(write_res.err().unwrap().to_string()),
-                );
-                return false;
-            } else {
-                write_res.unwrap();
-            }
+        let custom = !answers.confirm("use_defaults", "are these defaults ok?", true)?;
+        answers.defaulted_setting(
+            &mut settings,
+            "server_address",
+            "what is your server address then?",
+            custom,
+        )?;
+        answers.defaulted_setting(
+            &mut settings,
+            "key_file",
+            "what is your key file then?",
+            custom,
+        )?;
+        if custom || answers.value("distrobox").is_some() {
+            settings.distrobox = answers.confirm(
+                "distrobox",
+                "will you be using distrobox for your attack environments?",
+                true,
+            )?;
         }
+        if settings.distrobox {
+            answers.setting(
+                &mut settings,
+                "templatebox",
+                "name of the distrobox you will use?",
+                true,
+            )?;
+        }
+        answers.setting(
+            &mut settings,
+            "current_files",
+            "full path to where you want your current project's files stored? example: /home/pyro/projects/current",
+            true,
+        )?;
+        answers.setting(
+            &mut settings,
+            "current_notes",
+            "full path to where you want your current project's notes stored example: /home/pyro/notes/current",
+            true,
+        )?;
+        answers.setting(
+            &mut settings,
+            "upcoming_files",
+            "full path to where you want your upcoming project's files stored example: /home/pyro/projects/upcoming",
+            true,
+        )?;
+        answers.setting(
+            &mut settings,
+            "upcoming_notes",
+            "full path to where you want your upcoming project's notes stored exmple: /home/pyro/notes/upcoming",
+            true,
+        )?;
+        answers.setting(
+            &mut settings,
+            "archive",
+            "full path to where you want archived projects kept? leave it blank if you don't archive projects. example: /home/pyro/archive",
+            false,
+        )?;
+        answers.setting(
+            &mut settings,
+            "tools",
+            "full path to where you store your custom tools (like those from github)?",
+            true,
+        )?;
+        answers.setting(
+            &mut settings,
+            "terminal",
+            "command used to launch your terminal while executing a command (for exmaple konsole in kde is konsole -e)?",
+            false,
+        )?;
+        settings.validate()?;
+        print_success("sweet, we have all we need, writing config file...");
+        settings.save(&config_file)?;
         print_success("excellent we have created the client's config file!");
         println!("creating projects config file and adding the default project...");
         project_config.push("default.conf");
-        let projects_file_res = File::create_new(project_config);
-        if projects_file_res.is_err() {
-            print_error(
-                "error creating project config file!",
-                Some(projects_file_res.err().unwrap().to_string()),
-            );
-            return false;
+        for key in settings::KEYS {
+            println!("{} : {}", key, settings.get(key).unwrap_or_default());
         }
-        for key in settings.keys() {
-            println!("{} : {}", key, settings[key]);
+        let mut boxname = String::from("none");
+        if settings.distrobox && settings.templatebox.is_some() {
+            boxname = settings.templatebox.clone().unwrap();
         }
-        let mut project_file = projects_file_res.unwrap();
-        let mut out_line = format!(
-            "name|default\nstage|current\nfiles|{}\nnotes|{}",
-            settings["current_files"], settings["current_notes"]
-        );
-        if settings["distrobox"] == "yes".to_string() {
-            out_line = format!("{}\nboxname|{}", out_line, settings["templatebox"]);
-        }
-        let write_res = project_file.write(out_line.as_bytes());
-        if write_res.is_err() {
-            print_error(
-                "error writing to projects config file!",
-                Some(write_res.err().unwrap().to_string()),
-            );
-            return false;
+        let default_project = Project {
+            name: String::from("default"),
+            stage: Stage::Current,
+            files: settings.current_files.clone().unwrap_or_default(),
+            notes: settings.current_notes.clone().unwrap_or_default(),
+            boxname,
+            config: project_config,
+            ..Default::default()
+        };
+        let save_res = default_project.save_project();
+        if save_res != "Success!" {
+            print_error("error writing to projects config file!", Some(save_res));
+            return Ok(false);
         }
     }
+    let key_path = settings.key_file.clone();
+    // swapping the key out from under an enrolled client locks it out of the
+    // server, so only do it when there's no key yet or when asked. overwriting
+    // the config doesn't count as asking.
+    if key_path.exists()
+        && !answers.confirm(
+            "new_key",
+            "would you like to generate a new key? the server will need to be given the new one",
+            false,
+        )?
+    {
+        print_success("client successfully installed!");
+        print_success("please re-run this tool to use it!");
+        return Ok(true);
+    }
     println!("generating a new key for encryption...");
     let key = crytpo::generate_key();
-    let mut key_path = config_folder.clone();
-    key_path.push("key");
-    if key_path.exists() {
-        let remove_res = remove_file(&key_path);
-        if remove_res.is_err() {
-            print_error(
-                "error removing keyfile",
-                Some(remove_res.err().unwrap().to_string()),
-            );
-            return false;
-        }
+    let mut passphrase = String::new();
+    if answers.value("key_passphrase").is_some() {
+        passphrase = answers.value("key_passphrase").unwrap();
+    } else if answers.confirm(
+        "protect_key",
+        "would you like to protect the key with a passphrase?",
+        false,
+    )? {
+        passphrase = keys::prompt_new_passphrase()?;
     }
-    let key_file_res = File::create_new(key_path);
-    if key_file_res.is_err() {
-        print_error(
-            "error making key file!",
-            Some(key_file_res.err().unwrap().to_string()),
-        );
-        return false;
+    let key_save_res = keys::save_key(&key_path, &key, &passphrase);
+    if key_save_res.is_err() {
+        print_error("error making key file!", key_save_res.err());
+        return Ok(false);
     }
-    let mut key_file = key_file_res.unwrap();
-    key_file.write(&key).unwrap();
     print_success("client successfully installed!");
     print_success("please re-run this tool to use it!");
-    return true;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read, write};
+    use std::path::Path;
+
+    fn unattended(dir: &Path, extra: &[(&str, &str)]) -> Answers {
+        let mut values = HashMap::new();
+        let path = |name: &str| dir.join(name).display().to_string();
+        values.insert(
+            String::from("server_address"),
+            String::from("127.0.0.1:31337"),
+        );
+        values.insert(String::from("key_file"), path("client/key"));
+        values.insert(String::from("distrobox"), String::from("no"));
+        values.insert(String::from("current_files"), path("projects/current"));
+        values.insert(String::from("current_notes"), path("notes/current"));
+        values.insert(String::from("upcoming_files"), path("projects/upcoming"));
+        values.insert(String::from("upcoming_notes"), path("notes/upcoming"));
+        values.insert(String::from("tools"), path("tools"));
+        for (key, value) in extra {
+            values.insert(key.to_string(), value.to_string());
+        }
+        Answers {
+            values,
+            interactive: false,
+        }
+    }
+
+    fn config_path(dir: &Path) -> PathBuf {
+        dir.join("client").join("config.conf")
+    }
+
+    #[test]
+    fn fresh_install_writes_config_project_and_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_path(dir.path());
+        assert_eq!(run_install(&config, &unattended(dir.path(), &[])), Ok(true));
+
+        let settings = Settings::load(&config).unwrap();
+        assert_eq!(settings.server_address, "127.0.0.1:31337");
+        assert!(!settings.distrobox);
+        assert_eq!(
+            settings.current_files,
+            Some(dir.path().join("projects/current"))
+        );
+        let default = Project::load(&dir.path().join("client/projects/default.conf")).unwrap();
+        assert_eq!(default.name, "default");
+        assert_eq!(default.files, dir.path().join("projects/current"));
+        assert!(dir.path().join("client/note_templates").is_dir());
+        assert_eq!(
+            keys::inspect_key_file(&dir.path().join("client/key")),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn missing_required_answer_fails_unattended() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut answers = unattended(dir.path(), &[]);
+        answers.values.remove("current_files");
+        let error = run_install(&config_path(dir.path()), &answers)
+            .err()
+            .unwrap();
+        assert!(error.contains("no answer for current_files"));
+        assert!(!dir.path().join("client/key").exists());
+    }
+
+    #[test]
+    fn protect_key_is_a_valid_answer() {
+        let dir = tempfile::tempdir().unwrap();
+        let answers_file = dir.path().join("answers.toml");
+        write(&answers_file, "protect_key = false\nnew_key = true\n").unwrap();
+        let values = read_answers_file(&answers_file).unwrap();
+        assert_eq!(values["protect_key"], "no");
+
+        write(&answers_file, "protect_the_key = false\n").unwrap();
+        assert!(read_answers_file(&answers_file).is_err());
+
+        let answers = unattended(dir.path(), &[("key_passphrase", "hunter2")]);
+        assert_eq!(run_install(&config_path(dir.path()), &answers), Ok(true));
+        assert_eq!(
+            keys::inspect_key_file(&dir.path().join("client/key")),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn overwrite_keeps_existing_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_path(dir.path());
+        assert_eq!(run_install(&config, &unattended(dir.path(), &[])), Ok(true));
+        let other = dir.path().join("client/projects/other.conf");
+        write(&other, "name = \"other\"\n").unwrap();
+
+        let answers = unattended(
+            dir.path(),
+            &[("overwrite", "yes"), ("server_address", "10.0.0.1:443")],
+        );
+        assert_eq!(run_install(&config, &answers), Ok(true));
+        assert!(other.exists());
+        assert_eq!(
+            Settings::load(&config).unwrap().server_address,
+            "10.0.0.1:443"
+        );
+    }
+
+    #[test]
+    fn rerun_keeps_the_key_unless_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_path(dir.path());
+        let key_path = dir.path().join("client/key");
+        assert_eq!(run_install(&config, &unattended(dir.path(), &[])), Ok(true));
+        let key = read(&key_path).unwrap();
+
+        let answers = unattended(dir.path(), &[("overwrite", "no"), ("new_key", "no")]);
+        assert_eq!(run_install(&config, &answers), Ok(true));
+        assert_eq!(read(&key_path).unwrap(), key);
+
+        let answers = unattended(dir.path(), &[("overwrite", "no"), ("new_key", "yes")]);
+        assert_eq!(run_install(&config, &answers), Ok(true));
+        assert_ne!(read(&key_path).unwrap(), key);
+    }
+
+    #[test]
+    fn overwrite_keeps_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_path(dir.path());
+        let key_path = dir.path().join("client/key");
+        assert_eq!(run_install(&config, &unattended(dir.path(), &[])), Ok(true));
+        let key = read(&key_path).unwrap();
+
+        let answers = unattended(dir.path(), &[("overwrite", "yes")]);
+        assert_eq!(run_install(&config, &answers), Ok(true));
+        assert_eq!(read(&key_path).unwrap(), key);
+    }
+
+    #[test]
+    fn protect_key_needs_a_passphrase_unattended() {
+        let dir = tempfile::tempdir().unwrap();
+        let answers = unattended(dir.path(), &[("protect_key", "yes")]);
+        let error = run_install(&config_path(dir.path()), &answers)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error,
+            "protect_key needs key_passphrase when running unattended"
+        );
+        assert!(!config_path(dir.path()).exists());
+        assert!(!dir.path().join("client/key").exists());
+    }
 }
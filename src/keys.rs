@@ -0,0 +1,495 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use sha2::{Digest, Sha256};
+use std::fs::{OpenOptions, Permissions, read, remove_file, rename, set_permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::crytpo;
+
+// protected key files start with this so we can tell them apart from the raw
+// 32 byte keys older installs wrote.
+const PROTECTED_MAGIC: &[u8] = b"TETANUSKEY1";
+const SALT_LEN: usize = 16;
+pub const DEFAULT_GRACE_HOURS: u64 = 72;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    // checked up front so slicing below can't land inside a multi byte char.
+    let text = text.trim();
+    if let Some((position, _)) = text.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex at position {}", position));
+    }
+    if !text.len().is_multiple_of(2) {
+        return Err(String::from("hex string has an odd number of characters"));
+    }
+    let mut bytes = Vec::new();
+    for id in (0..text.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&text[id..id + 2], 16).unwrap());
+    }
+    Ok(bytes)
+}
+
+// short, human comparable id for a key. safe to read out over the phone.
+pub fn fingerprint(key: &[u8]) -> String {
+    let digest = Sha256::digest(key);
+    let hex = to_hex(&digest[..16]);
+    let groups: Vec<&str> = (0..hex.len())
+        .step_by(4)
+        .map(|id| &hex[id..id + 4])
+        .collect();
+    groups.join(":")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn validate_key(key: &[u8]) -> Result<(), String> {
+    if key.len() != crytpo::KEY_LEN {
+        return Err(format!(
+            "key must be exactly {} bytes, this one is {}",
+            crytpo::KEY_LEN,
+            key.len()
+        ));
+    }
+    Ok(())
+}
+
+pub fn is_protected(contents: &[u8]) -> bool {
+    contents.starts_with(PROTECTED_MAGIC)
+}
+
+fn derive_kek(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, String> {
+    let mut kek = vec![0u8; crytpo::KEY_LEN];
+    let derive_res = Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut kek);
+    if derive_res.is_err() {
+        return Err(format!(
+            "error deriving key from passphrase!\n{}",
+            derive_res.err().unwrap()
+        ));
+    }
+    Ok(kek)
+}
+
+// layout is magic || salt || nonce || sealed key. the magic and salt ride
+// along as associated data so neither can be swapped without failing auth.
+pub fn wrap_key(key: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let kek = derive_kek(passphrase, &salt)?;
+    let header = [PROTECTED_MAGIC, &salt].concat();
+    let sealed_res = crytpo::encrypt_bytes(key, &kek, &header);
+    if sealed_res.is_err() {
+        return Err(format!(
+            "error encrypting key!\n{}",
+            sealed_res.err().unwrap()
+        ));
+    }
+    Ok([header, sealed_res.unwrap()].concat())
+}
+
+pub fn unwrap_key(contents: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    if !is_protected(contents) || contents.len() < PROTECTED_MAGIC.len() + SALT_LEN {
+        return Err(String::from("key file is not passphrase protected"));
+    }
+    let (header, sealed) = contents.split_at(PROTECTED_MAGIC.len() + SALT_LEN);
+    let kek = derive_kek(passphrase, &header[PROTECTED_MAGIC.len()..])?;
+    let key_res = crytpo::decrypt_bytes(sealed, &kek, header);
+    if key_res.is_err() {
+        return Err(String::from(
+            "wrong passphrase, or the key file has been tampered with",
+        ));
+    }
+    Ok(key_res.unwrap())
+}
+
+// no retrying, without a terminal (systemd, ci) every try fails the same way.
+pub fn prompt_passphrase(prompt: &str) -> Result<String, String> {
+    rpassword::prompt_password(prompt).map_err(|e| format!("error reading passphrase!\n{}", e))
+}
+
+// asks twice so a typo doesn't lock someone out of their own key.
+pub fn prompt_new_passphrase() -> Result<String, String> {
+    loop {
+        let passphrase = prompt_passphrase("new passphrase (leave blank for none): ")?;
+        let confirm = prompt_passphrase("confirm passphrase: ")?;
+        if passphrase == confirm {
+            return Ok(passphrase);
+        }
+        crate::print_error("passphrases didn't match, try again...", None);
+    }
+}
+
+pub fn check_permissions(path: &Path) -> Result<(), String> {
+    let meta_res = path.metadata();
+    if meta_res.is_err() {
+        return Err(format!(
+            "error reading key file permissions!\n{}",
+            meta_res.err().unwrap()
+        ));
+    }
+    let mode = meta_res.unwrap().permissions().mode();
+    if mode & 0o004 != 0 {
+        return Err(format!(
+            "key file {} is world readable (mode {:o})! fix it with chmod 600 {}",
+            path.display(),
+            mode & 0o777,
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+// writes to a temp file next to the real one and renames it over the top so
+// a crash half way through never leaves us with a truncated key.
+pub fn write_key_file(path: &PathBuf, contents: &[u8]) -> Result<(), String> {
+    let mut temp_path = path.clone();
+    temp_path.set_extension("tmp");
+    let file_res = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_path);
+    if file_res.is_err() {
+        return Err(format!(
+            "error creating key file!\n{}",
+            file_res.err().unwrap()
+        ));
+    }
+    let mut key_file = file_res.unwrap();
+    let write_res = key_file.write_all(contents);
+    if write_res.is_err() {
+        return Err(format!(
+            "error writing key file!\n{}",
+            write_res.err().unwrap()
+        ));
+    }
+    let _ = set_permissions(&temp_path, Permissions::from_mode(0o600));
+    let rename_res = rename(&temp_path, path);
+    if rename_res.is_err() {
+        return Err(format!(
+            "error moving new key file into place!\n{}",
+            rename_res.err().unwrap()
+        ));
+    }
+    Ok(())
+}
+
+pub fn save_key(path: &PathBuf, key: &[u8], passphrase: &str) -> Result<(), String> {
+    if passphrase.is_empty() {
+        return write_key_file(path, key);
+    }
+    let wrapped = wrap_key(key, passphrase)?;
+    write_key_file(path, &wrapped)
+}
+
+fn open_key(contents: &[u8], passphrase: &mut Option<String>) -> Result<Vec<u8>, String> {
+    let mut key = contents.to_vec();
+    if is_protected(contents) {
+        if passphrase.is_none() {
+            *passphrase = Some(prompt_passphrase("key passphrase: ")?);
+        }
+        key = unwrap_key(contents, passphrase.as_ref().unwrap())?;
+    }
+    validate_key(&key)?;
+    Ok(key)
+}
+
+// reads the key, asking for the passphrase if the file is protected. gives
+// back the passphrase too so the key can be saved again the same way.
+pub fn load_key(path: &PathBuf) -> Result<(Vec<u8>, String), String> {
+    check_permissions(path)?;
+    let read_res = read(path);
+    if read_res.is_err() {
+        return Err(format!(
+            "error reading key file!\n{}",
+            read_res.err().unwrap()
+        ));
+    }
+    let mut passphrase = None;
+    let key = open_key(&read_res.unwrap(), &mut passphrase)?;
+    Ok((key, passphrase.unwrap_or_default()))
+}
+
+// sanity checks a key file without asking for the passphrase. gives back
+// whether the key is passphrase protected.
+pub fn inspect_key_file(path: &PathBuf) -> Result<bool, String> {
+    let read_res = read(path);
+    if read_res.is_err() {
+        return Err(format!(
+            "error reading key file!\n{}",
+            read_res.err().unwrap()
+        ));
+    }
+    let contents = read_res.unwrap();
+    if is_protected(&contents) {
+        let expected = PROTECTED_MAGIC.len() + SALT_LEN + crytpo::sealed_len(crytpo::KEY_LEN);
+        if contents.len() != expected {
+            return Err(format!(
+                "protected key file should be {} bytes, this one is {}",
+                expected,
+                contents.len()
+            ));
+        }
+        return Ok(true);
+    }
+    validate_key(&contents)?;
+    Ok(false)
+}
+
+pub fn change_passphrase(path: &PathBuf) -> Result<(), String> {
+    let keyring = KeyRing::load(path)?;
+    let passphrase = prompt_new_passphrase()?;
+    keyring.set_passphrase(passphrase)
+}
+
+fn previous_path(path: &Path) -> PathBuf {
+    path.with_extension("previous")
+}
+
+// the previous key file is its expiry time followed by the key, saved the same
+// way as the main key file.
+fn save_previous(path: &Path, key: &[u8], expires: u64, passphrase: &str) -> Result<(), String> {
+    let mut contents = key.to_vec();
+    if !passphrase.is_empty() {
+        contents = wrap_key(key, passphrase)?;
+    }
+    let contents = [expires.to_be_bytes().to_vec(), contents].concat();
+    write_key_file(&previous_path(path), &contents)
+}
+
+struct KeyRingState {
+    path: PathBuf,
+    passphrase: String,
+    current: Vec<u8>,
+    previous: Option<(Vec<u8>, u64)>,
+}
+
+// the key in use plus, for a while after a rotation or import, the one it
+// replaced. the old key is kept so we can still get in if the server hasn't
+// caught up yet. clones all share the same keys.
+#[derive(Clone)]
+pub struct KeyRing {
+    state: Arc<Mutex<KeyRingState>>,
+}
+
+impl KeyRing {
+    pub fn load(path: &PathBuf) -> Result<KeyRing, String> {
+        let (current, passphrase) = load_key(path)?;
+        let mut previous = None;
+        let previous_path = previous_path(path);
+        if previous_path.exists() {
+            let read_res = read(&previous_path);
+            if let Ok(contents) = read_res
+                && contents.len() > 8
+            {
+                let expires = u64::from_be_bytes(contents[..8].try_into().unwrap());
+                let mut known_passphrase = Some(passphrase.clone());
+                match open_key(&contents[8..], &mut known_passphrase) {
+                    Ok(key) if expires > now() => previous = Some((key, expires)),
+                    _ => {
+                        let _ = remove_file(&previous_path);
+                    }
+                }
+            }
+        }
+        Ok(KeyRing {
+            state: Arc::new(Mutex::new(KeyRingState {
+                path: path.clone(),
+                passphrase,
+                current,
+                previous,
+            })),
+        })
+    }
+
+    pub fn current(&self) -> Vec<u8> {
+        self.state.lock().unwrap().current.clone()
+    }
+
+    pub fn previous(&self) -> Option<(Vec<u8>, u64)> {
+        let state = self.state.lock().unwrap();
+        if state.previous.is_some() && state.previous.as_ref().unwrap().1 <= now() {
+            return None;
+        }
+        state.previous.clone()
+    }
+
+    // keys worth trying when connecting, newest first.
+    pub fn candidates(&self) -> Vec<Vec<u8>> {
+        let mut keys = vec![self.current()];
+        if let Some((previous, _)) = self.previous() {
+            keys.push(previous);
+        }
+        keys
+    }
+
+    pub fn passphrase(&self) -> String {
+        self.state.lock().unwrap().passphrase.clone()
+    }
+
+    // swaps in a new key and keeps the old one around for the grace period.
+    pub fn replace(&self, new_key: Vec<u8>, grace: Duration) -> Result<(), String> {
+        validate_key(&new_key)?;
+        let mut state = self.state.lock().unwrap();
+        let expires = now() + grace.as_secs();
+        let old_key = state.current.clone();
+        let previous_path = previous_path(&state.path);
+        if grace.as_secs() > 0 {
+            save_previous(&state.path, &old_key, expires, &state.passphrase)?;
+        } else if previous_path.exists() {
+            let _ = remove_file(&previous_path);
+        }
+        save_key(&state.path, &new_key, &state.passphrase)?;
+        state.current = new_key;
+        if grace.as_secs() > 0 {
+            state.previous = Some((old_key, expires));
+        } else {
+            state.previous = None;
+        }
+        Ok(())
+    }
+
+    pub fn set_passphrase(&self, passphrase: String) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        save_key(&state.path, &state.current, &passphrase)?;
+        if state.previous.is_some() {
+            let (old_key, expires) = state.previous.clone().unwrap();
+            save_previous(&state.path, &old_key, expires, &passphrase)?;
+        }
+        state.passphrase = passphrase;
+        Ok(())
+    }
+}
+
+pub fn server_identity_path(config: &Path) -> PathBuf {
+    config.with_file_name("server_identity")
+}
+
+// the server's identity key we were handed at enrollment. if it's there,
+// every later connection has to present the same one.
+pub fn load_server_identity(config: &Path) -> Result<Option<Vec<u8>>, String> {
+    let path = server_identity_path(config);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let read_res = std::fs::read_to_string(&path);
+    if read_res.is_err() {
+        return Err(format!(
+            "error reading pinned server identity!\n{}",
+            read_res.err().unwrap()
+        ));
+    }
+    Ok(Some(from_hex(&read_res.unwrap())?))
+}
+
+pub fn save_server_identity(config: &Path, identity: &[u8]) -> Result<(), String> {
+    write_key_file(&server_identity_path(config), to_hex(identity).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_round_trips() {
+        let bytes = vec![0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(from_hex(&to_hex(&bytes)).unwrap(), bytes);
+        assert_eq!(from_hex(" 00ff\n").unwrap(), vec![0x00, 0xff]);
+    }
+
+    #[test]
+    fn from_hex_rejects_junk_without_panicking() {
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        // multi byte chars used to get sliced through the middle.
+        assert!(from_hex("aé").is_err());
+        assert!(from_hex("éa").is_err());
+        assert!(from_hex("ab🦀").is_err());
+    }
+
+    #[test]
+    fn wrapped_key_needs_the_right_passphrase() {
+        let key = crytpo::generate_key();
+        let wrapped = wrap_key(&key, "hunter2").unwrap();
+        assert!(is_protected(&wrapped));
+        assert_eq!(unwrap_key(&wrapped, "hunter2").unwrap(), key);
+        assert!(unwrap_key(&wrapped, "hunter3").is_err());
+        assert!(unwrap_key(&key, "hunter2").is_err());
+    }
+
+    #[test]
+    fn tampered_header_is_rejected() {
+        let key = crytpo::generate_key();
+        let wrapped = wrap_key(&key, "hunter2").unwrap();
+        let mut salted = wrapped.clone();
+        salted[PROTECTED_MAGIC.len()] ^= 0x01;
+        assert!(unwrap_key(&salted, "hunter2").is_err());
+        let mut unmarked = wrapped.clone();
+        unmarked[0] ^= 0x01;
+        assert_eq!(
+            unwrap_key(&unmarked, "hunter2"),
+            Err(String::from("key file is not passphrase protected"))
+        );
+    }
+
+    #[test]
+    fn world_readable_key_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        save_key(&path, &crytpo::generate_key(), "").unwrap();
+        assert!(check_permissions(&path).is_ok());
+        set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        assert!(check_permissions(&path).is_err());
+        assert!(load_key(&path).is_err());
+    }
+
+    #[test]
+    fn previous_key_is_kept_for_the_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        let old_key = crytpo::generate_key();
+        let new_key = crytpo::generate_key();
+        save_key(&path, &old_key, "").unwrap();
+        let keyring = KeyRing::load(&path).unwrap();
+        assert_eq!(keyring.candidates(), vec![old_key.clone()]);
+
+        keyring
+            .replace(new_key.clone(), Duration::from_secs(3600))
+            .unwrap();
+        assert_eq!(keyring.candidates(), vec![new_key.clone(), old_key.clone()]);
+        let reloaded = KeyRing::load(&path).unwrap();
+        assert_eq!(reloaded.current(), new_key);
+        assert_eq!(reloaded.previous().unwrap().0, old_key);
+
+        keyring.replace(old_key.clone(), Duration::ZERO).unwrap();
+        assert_eq!(keyring.previous(), None);
+        assert!(!previous_path(&path).exists());
+    }
+
+    #[test]
+    fn expired_previous_key_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        let old_key = crytpo::generate_key();
+        save_key(&path, &crytpo::generate_key(), "").unwrap();
+        save_previous(&path, &old_key, now() - 1, "").unwrap();
+
+        let keyring = KeyRing::load(&path).unwrap();
+        assert_eq!(keyring.previous(), None);
+        assert_eq!(keyring.candidates().len(), 1);
+        assert!(!previous_path(&path).exists());
+    }
+}
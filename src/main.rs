@@ -3,13 +3,11 @@ use chacha20poly1305::aead::generic_array::typenum::Unsigned;
 use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
 use chacha20poly1305::{ChaCha20Poly1305, Key};
 use clap::Parser;
-use colored::Colorize;
 use num_cpus;
-use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions, read_dir, read_to_string};
 use std::io::{Read, Write};
 use std::os::unix::net;
-use std::path::{Display, PathBuf};
+use std::path::{Path, PathBuf};
 use std::process::{Output, exit};
 use std::thread::sleep;
 use std::time::Duration;
@@ -17,13 +15,19 @@ use std::{thread, time};
 use tokio;
 use tokio::io::AsyncBufReadExt;
 use tokio::sync::mpsc::{Receiver, Sender, channel};
+use tokio::sync::watch;
+
+use tetanus_client::{
+    crytpo, keys, mock_server, network, print_error, print_success, project, protocol, table,
+};
 
 mod cli;
 mod commands;
-mod crytpo;
+mod doctor;
 mod install;
-mod lib;
-mod network;
+mod profiles;
+mod settings;
+mod templates;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -45,26 +49,60 @@ struct Args {
     #[arg(
         short,
         long,
-        help = "a path to a custom config file, defaults to ~/.config/tetanus/clients/main_attacker.conf"
+        help = "a path to a custom config file, defaults to ~/.config/tetanus/clients/<name>/config.conf"
     )]
     config: Option<PathBuf>,
 
     #[arg(short, long, help = "generate or re-generate the config file")]
     install: bool,
 
-    #[arg(short, long, help = "custom name to give this client.")]
+    #[arg(
+        short,
+        long,
+        help = "the profile to use and the name this client gives the server, defaults to main_attacker"
+    )]
     name: Option<String>,
-}
 
-pub fn print_success(text: &str) {
-    println!("{}", text.green());
-}
+    #[arg(
+        long,
+        help = "set, change or remove the passphrase protecting the key file"
+    )]
+    change_passphrase: bool,
 
-pub fn print_error(text: &str, error: Option<String>) {
-    println!("{}", text.red());
-    if error.is_some() {
-        println!("{}", error.unwrap().red());
-    }
+    #[arg(
+        short,
+        long,
+        help = "register this client's name and key with the server using a one-time enrollment token"
+    )]
+    enroll: Option<String>,
+
+    #[arg(
+        long,
+        help = "run a mock tetanus server on the server address for local development, using this client's key"
+    )]
+    mock_server: bool,
+
+    #[arg(
+        long,
+        help = "check the config, key, distrobox setup and server then exit"
+    )]
+    doctor: bool,
+
+    #[arg(
+        long,
+        help = "install any new bundled note templates, leaving ones you've edited alone, then exit"
+    )]
+    upgrade_templates: bool,
+
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        help = "override a setting for this run. precedence is config file < TETANUS_<KEY> environment variables < --set < --server"
+    )]
+    set: Vec<String>,
+
+    #[command(flatten)]
+    install_args: install::InstallArgs,
 }
 
 pub fn get_user_input(prompt: &str) -> String {
@@ -81,8 +119,8 @@ pub fn get_user_input(prompt: &str) -> String {
     return response.trim().to_string();
 }
 
-pub fn load_projects(path: &PathBuf, display: bool) -> Vec<lib::Project> {
-    let mut projects_path = path.clone();
+pub fn load_projects(path: &Path, display: bool) -> Vec<project::Project> {
+    let mut projects_path = path.to_path_buf();
     projects_path.pop();
     projects_path.push("projects");
     let project_dir_res = read_dir(projects_path);
@@ -97,59 +135,17 @@ pub fn load_projects(path: &PathBuf, display: bool) -> Vec<lib::Project> {
     let mut projects = Vec::new();
     for res in project_dir {
         if res.is_ok() {
-            let mut new_project = lib::Project::default();
             let entry = res.unwrap();
             let file_name = entry.file_name().to_string_lossy().to_string();
-            if file_name.contains(".conf") {
-                let conf_string_res = read_to_string(entry.path());
-                if conf_string_res.is_ok() {
-                    let conf_string = conf_string_res.unwrap();
-                    for line in conf_string.lines() {
-                        if line.len() > 2 {
-                            let line_vec: Vec<&str> = line.split("|").collect();
-                            match line_vec[0] {
-                                "name" => {
-                                    new_project.name = line_vec[1].trim().to_string();
-                                }
-                                "stage" => {
-                                    if line_vec[1].contains("current") {
-                                        new_project.current = true;
-                                    } else {
-                                        new_project.current = false;
-                                    }
-                                }
-                                "files" => {
-                                    new_project.files = PathBuf::from(line_vec[1]);
-                                }
-                                "notes" => {
-                                    new_project.notes = PathBuf::from(line_vec[1]);
-                                }
-                                "boxname" => new_project.boxname = String::from(line_vec[1]),
-                                "config" => new_project.config = PathBuf::from(line_vec[1]),
-                                _ => {
-                                    print_error(
-                                        "unknown setting discoverd in project config file!",
-                                        Some(String::from(line_vec[0])),
-                                    );
-                                }
-                            }
-                        }
-                    }
-                    if new_project.boxname.len() > 0 {
-                        projects.push(new_project);
-                    } else {
-                        new_project.boxname = String::from("none");
-                        projects.push(new_project);
-                    }
-                    if display {
-                        print_success(
-                            format!(
-                                "{} successfully loaded!",
-                                entry.file_name().to_string_lossy()
-                            )
-                            .as_str(),
-                        );
-                    }
+            if file_name.ends_with(".conf") {
+                let project_res = project::Project::load(&entry.path());
+                if project_res.is_err() {
+                    print_error("error loading project!", project_res.err());
+                    continue;
+                }
+                projects.push(project_res.unwrap());
+                if display {
+                    print_success(format!("{} successfully loaded!", file_name).as_str());
                 }
             }
         }
@@ -157,26 +153,30 @@ pub fn load_projects(path: &PathBuf, display: bool) -> Vec<lib::Project> {
     return projects;
 }
 
-pub fn load_settings(config_path: &PathBuf, display: bool) -> HashMap<String, String> {
-    let mut settings = HashMap::new();
-    let conf_read_res = read_to_string(&config_path);
-    if conf_read_res.is_err() {
-        print_error(
-            "error reading config file!",
-            Some(conf_read_res.err().unwrap().to_string()),
-        );
+pub fn load_settings(
+    config_path: &Path,
+    overrides: &settings::Overrides,
+    display: bool,
+) -> settings::Settings {
+    let settings_res = settings::Settings::load_with(config_path, overrides);
+    if settings_res.is_err() {
+        print_error("error loading settings!", settings_res.err());
         exit(1);
     }
-    let conf_string = conf_read_res.unwrap();
+    let settings = settings_res.unwrap();
     if display {
-        println!("loading settings from config line...");
-    }
-    for line in conf_string.lines() {
-        if line.contains("|") {
-            let line_vec: Vec<&str> = line.split("|").collect();
-            settings.insert(line_vec[0].to_string(), line_vec[1].to_string());
-            if display {
-                print_success(format!("{} {} LOADED!", line_vec[0], line_vec[1]).as_str());
+        println!("loading settings from config file...");
+        let file_keys = settings::file_keys(config_path);
+        for key in settings::KEYS {
+            if let Some(value) = settings.get(key) {
+                let source = overrides.source(key, &file_keys);
+                if source == settings::Source::Env || source == settings::Source::Flag {
+                    print_success(
+                        format!("{} {} LOADED! ({})", key, value, source.as_str()).as_str(),
+                    );
+                } else {
+                    print_success(format!("{} {} LOADED!", key, value).as_str());
+                }
             }
         }
     }
@@ -186,7 +186,6 @@ pub fn load_settings(config_path: &PathBuf, display: bool) -> HashMap<String, St
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    let mut settings = HashMap::new();
     let mut config_path = PathBuf::new();
     let mut server_address = String::from("127.0.0.1:31337");
     if args.config.is_some() {
@@ -195,7 +194,13 @@ async fn main() {
         let home_res = std::env::home_dir();
         if home_res.is_some() {
             config_path = home_res.unwrap();
-            config_path.push(".config/tetanus/clients/main_attacker/config.conf");
+            config_path.push(".config/tetanus/clients");
+            config_path.push(
+                args.name
+                    .clone()
+                    .unwrap_or(String::from(profiles::DEFAULT_PROFILE)),
+            );
+            config_path.push(profiles::CONFIG_FILE);
         } else {
             print_error(
                 "error finding config file!\nplease re-run while specifying a config file",
@@ -204,10 +209,16 @@ async fn main() {
         }
     }
     if args.install {
-        let res = install::install(&config_path);
-        if res {
-            print_success("client successfully installed!");
-            print_success("please re-run this tool to use it!");
+        let answers_res = install::Answers::from_args(&args.install_args, args.server.clone());
+        if answers_res.is_err() {
+            print_error("error reading install answers!", answers_res.err());
+            exit(1);
+        }
+        let res = install::install(&config_path, &answers_res.unwrap());
+        if !res {
+            exit(1);
+        }
+        if args.enroll.is_none() {
             exit(0);
         }
     } else if !config_path.exists() {
@@ -216,65 +227,140 @@ async fn main() {
             .to_lowercase()
             .contains("y")
         {
-            let status = install::install(&config_path);
+            let status = install::install(&config_path, &install::Answers::interactive());
             if !status {
                 print_error("error installing...", None);
                 exit(1);
-            } else {
-                print_success("client successfully installed!");
-                print_success("please re-run this tool to use it!");
-                exit(0);
             }
+            exit(0);
         } else {
             print_error("config file does not exist", None);
             exit(1);
         }
     }
 
-    let conf_read_res = read_to_string(&config_path);
-    if conf_read_res.is_err() {
-        print_error(
-            "error reading config file!",
-            Some(conf_read_res.err().unwrap().to_string()),
-        );
+    let overrides_res = settings::Overrides::new(&args.set, args.server.clone());
+    if overrides_res.is_err() {
+        print_error("error reading setting overrides!", overrides_res.err());
         exit(1);
     }
-    let conf_string = conf_read_res.unwrap();
-    println!("loading settings from config line...");
-    for line in conf_string.lines() {
-        if line.contains("|") {
-            let line_vec: Vec<&str> = line.split("|").collect();
-            settings.insert(line_vec[0].to_string(), line_vec[1].to_string());
-            print_success(format!("{} {} LOADED!", line_vec[0], line_vec[1]).as_str());
+    let overrides = overrides_res.unwrap();
+    if args.doctor {
+        let checks = doctor::run(&config_path, &overrides);
+        println!("{}", doctor::report(&checks));
+        if doctor::failed(&checks) {
+            exit(1);
         }
+        exit(0);
     }
-    let key_path = PathBuf::from(settings["key_file"].clone());
-    let mut key_vec = Vec::new();
-    let key_open_res = OpenOptions::new().read(true).open(key_path);
-    if key_open_res.is_err() {
-        print_error(
-            "error opening key file!",
-            Some(key_open_res.err().unwrap().to_string()),
-        );
+    let settings = load_settings(&config_path, &overrides, true);
+    let templates_path = templates::templates_dir(&config_path);
+    if args.upgrade_templates || !templates::is_installed(&templates_path) {
+        let upgrade_res = templates::upgrade(&templates_path);
+        if upgrade_res.is_err() {
+            print_error("error upgrading note templates!", upgrade_res.err());
+            exit(1);
+        }
+        print_success(&upgrade_res.unwrap().summary());
+        if args.upgrade_templates {
+            exit(0);
+        }
+    }
+    let key_path = settings.key_file.clone();
+    if args.change_passphrase {
+        let change_res = keys::change_passphrase(&key_path);
+        if change_res.is_err() {
+            print_error("error changing passphrase!", change_res.err());
+            exit(1);
+        }
+        print_success("key passphrase updated!");
+        exit(0);
+    }
+    let keyring_res = keys::KeyRing::load(&key_path);
+    if keyring_res.is_err() {
+        print_error("error loading key!", keyring_res.err());
         exit(1);
     }
-    let mut key_file = key_open_res.unwrap();
-    let key_read_res = key_file.read(&mut key_vec);
-    if key_read_res.is_err() {
-        print_error(
-            "error reading key",
-            Some(key_read_res.err().unwrap().to_string()),
+    let keyring = keyring_res.unwrap();
+    print_success(format!("key {} LOADED!", keys::fingerprint(&keyring.current())).as_str());
+
+    let server_address = settings.server_address.clone();
+    let mut client_name = String::from("main_attacker");
+    if args.name.is_some() {
+        client_name = args.name.clone().unwrap();
+    } else if config_path
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .is_some()
+    {
+        client_name = config_path
+            .parent()
+            .unwrap()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+    }
+    if args.mock_server {
+        let identity_res = mock_server::MockServer::new(&keyring.current())
+            .with_demo_script()
+            .with_identity_file(&mock_server::identity_path(&config_path));
+        if identity_res.is_err() {
+            print_error("error loading mock server identity!", identity_res.err());
+            exit(1);
+        }
+        let mut mock = identity_res.unwrap();
+        mock.enrollment_token = args.enroll.clone();
+        let bind_res = mock.bind(&server_address).await;
+        if bind_res.is_err() {
+            print_error(
+                "error starting mock server!",
+                Some(bind_res.err().unwrap().to_string()),
+            );
+            exit(1);
+        }
+        let (listener, local) = bind_res.unwrap();
+        print_success(format!("mock server listening on {}", local).as_str());
+        mock.serve(listener).await;
+        exit(0);
+    }
+    if let Some(token) = args.enroll {
+        println!("enrolling {} with {}...", client_name, server_address);
+        let enroll_res =
+            network::enroll(&server_address, &client_name, &token, &keyring.current()).await;
+        if enroll_res.is_err() {
+            print_error(
+                "error enrolling with the server!",
+                Some(enroll_res.err().unwrap().to_string()),
+            );
+            exit(1);
+        }
+        let identity = enroll_res.unwrap();
+        let pin_res = keys::save_server_identity(&config_path, &identity);
+        if pin_res.is_err() {
+            print_error("error saving the server identity!", pin_res.err());
+            exit(1);
+        }
+        print_success(
+            format!(
+                "enrolled! server identity {} pinned",
+                keys::fingerprint(&identity)
+            )
+            .as_str(),
         );
+        exit(0);
+    }
+    let pinned_res = keys::load_server_identity(&config_path);
+    if pinned_res.is_err() {
+        print_error("error loading server identity!", pinned_res.err());
         exit(1);
     }
-    key_read_res.unwrap();
+    let pinned_identity = pinned_res.unwrap();
 
     let projects = load_projects(&config_path, true);
-    let mut server_address = String::from("127.0.0.1:31337");
-    if args.server.is_some() {
-        server_address = args.server.unwrap();
-    }
     let (main_tx, mut main_rx) = channel(1024);
+    let (server_tx, server_rx) = channel(1024);
+    let (state_tx, state_rx) = watch::channel(network::ConnectionState::Down);
     let (console_tx, console_rx) = channel(1024);
     let cpu_count = num_cpus::get_physical();
     let thread_count = cpu_count / 2;
@@ -288,11 +374,24 @@ async fn main() {
         println!("coming soon!");
     } else {
         let rt_handle = runtime.handle();
+        runtime.spawn(network::connection_manager(
+            server_address,
+            client_name,
+            keyring.clone(),
+            pinned_identity,
+            server_rx,
+            console_tx.clone(),
+            state_tx,
+        ));
         let handle = runtime.spawn(cli::cli(
             console_tx,
             console_rx,
             main_tx,
+            server_tx,
+            state_rx,
+            keyring,
             config_path.clone(),
+            overrides,
             rt_handle.clone(),
         ));
         loop {
@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use x25519_dalek::StaticSecret;
+
+use crate::crytpo;
+use crate::keys;
+use crate::network::{
+    FrameCodec, auth_transcript, enrollment_salt, enrollment_transcript, read_frame,
+    read_sealed_frame, session_salt, write_frame,
+};
+use crate::protocol::ServerMessage;
+use crate::{print_error, print_success};
+
+// where --mock-server keeps its identity, next to the client config.
+pub fn identity_path(config: &Path) -> PathBuf {
+    config.with_file_name("mock_identity")
+}
+
+// a stand in for the real tetanus server. it speaks the same protocol as the
+// client, answers commands from a script (or just echoes them), pushes fake
+// agent events and remembers everything it was sent so it can be checked
+// afterwards.
+#[derive(Clone)]
+pub struct MockServer {
+    pub key: Arc<Mutex<Vec<u8>>>,
+    identity: StaticSecret,
+    // the identity we tell clients we have. only differs from the real one
+    // when a test wants an impostor.
+    pub identity_key: Vec<u8>,
+    pub enrollment_token: Option<String>,
+    pub script: HashMap<String, String>,
+    pub events: Vec<ServerMessage>,
+    pub event_interval: Option<Duration>,
+    // a command that makes the server drop the connection instead of
+    // answering, to see how clients cope with a server going away.
+    pub hang_up_on: Option<String>,
+    pub received: Arc<Mutex<Vec<ServerMessage>>>,
+    seen_challenges: Arc<Mutex<HashSet<Vec<u8>>>>,
+}
+
+impl MockServer {
+    pub fn new(key: &[u8]) -> Self {
+        let (identity, identity_key) = crytpo::generate_identity();
+        Self {
+            key: Arc::new(Mutex::new(key.to_vec())),
+            identity,
+            identity_key,
+            enrollment_token: None,
+            script: HashMap::new(),
+            events: Vec::new(),
+            event_interval: None,
+            hang_up_on: None,
+            received: Arc::new(Mutex::new(Vec::new())),
+            seen_challenges: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // swaps in the identity saved at path, or saves ours there if there isn't
+    // one yet, so clients that pinned it can still get in after a restart.
+    pub fn with_identity_file(mut self, path: &PathBuf) -> Result<Self, String> {
+        if path.exists() {
+            let contents = match std::fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) => return Err(format!("error reading mock server identity!\n{}", e)),
+            };
+            let secret = keys::from_hex(&contents)?;
+            (self.identity, self.identity_key) = crytpo::identity_from_secret(&secret)?;
+        } else {
+            keys::write_key_file(path, keys::to_hex(self.identity.as_bytes()).as_bytes())?;
+        }
+        Ok(self)
+    }
+
+    // what a dev box gets when running --mock-server.
+    pub fn with_demo_script(mut self) -> Self {
+        self.script
+            .insert(String::from("ping"), String::from("pong"));
+        self.events = vec![
+            ServerMessage::Event {
+                kind: String::from("agent_checkin"),
+                content: String::from("mock-agent-01 (10.0.0.23) checked in"),
+            },
+            ServerMessage::Event {
+                kind: String::from("chat"),
+                content: String::from("operator: welcome to the mock server"),
+            },
+        ];
+        self.event_interval = Some(Duration::from_secs(60));
+        self
+    }
+
+    pub fn received(&self) -> Vec<ServerMessage> {
+        self.received.lock().unwrap().clone()
+    }
+
+    pub fn current_key(&self) -> Vec<u8> {
+        self.key.lock().unwrap().clone()
+    }
+
+    pub async fn bind(&self, address: &str) -> Result<(TcpListener, SocketAddr), Error> {
+        let listener = TcpListener::bind(address).await?;
+        let local = listener.local_addr()?;
+        Ok((listener, local))
+    }
+
+    // accepts clients until the listener errors out. each client gets its
+    // own task so one bad connection can't stall the rest.
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            let accept_res = listener.accept().await;
+            if accept_res.is_err() {
+                print_error(
+                    "mock server stopped accepting clients",
+                    Some(accept_res.err().unwrap().to_string()),
+                );
+                return;
+            }
+            let (stream, peer) = accept_res.unwrap();
+            let server = self.clone();
+            tokio::spawn(async move {
+                let handle_res = server.handle(stream).await;
+                if handle_res.is_err() {
+                    let error = handle_res.err().unwrap();
+                    if error.kind() != ErrorKind::UnexpectedEof {
+                        print_error(
+                            &format!("mock server dropped {}", peer),
+                            Some(error.to_string()),
+                        );
+                    }
+                }
+            });
+        }
+    }
+
+    fn record(&self, message: &ServerMessage) {
+        self.received.lock().unwrap().push(message.clone());
+    }
+
+    async fn read(
+        &self,
+        stream: &mut TcpStream,
+        codec: &FrameCodec,
+    ) -> Result<ServerMessage, Error> {
+        let message = read_frame(stream, codec).await?;
+        self.record(&message);
+        Ok(message)
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> Result<(), Error> {
+        // the first frame tells us what kind of client this is. enrolling
+        // clients seal it with the token, everyone else with their key.
+        let sealed = read_sealed_frame(&mut stream).await?;
+        let key_codec = FrameCodec::server(&self.current_key());
+        if let Ok(hello) = key_codec.open(&sealed) {
+            self.record(&hello);
+            write_frame(&mut stream, &key_codec, &ServerMessage::hello()).await?;
+            let codec = self.authenticate(&mut stream, &key_codec).await?;
+            return self.session(stream, &codec).await;
+        }
+        if let Some(token) = &self.enrollment_token {
+            let token_key = crytpo::derive_enrollment_key(token);
+            let token_codec = FrameCodec::server(&token_key);
+            if let Ok(hello) = token_codec.open(&sealed) {
+                self.record(&hello);
+                write_frame(&mut stream, &token_codec, &ServerMessage::hello()).await?;
+                return self.enroll(&mut stream, &token_codec, &token_key).await;
+            }
+        }
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "client hello was not sealed with a key we know",
+        ))
+    }
+
+    async fn refuse(
+        &self,
+        stream: &mut TcpStream,
+        codec: &FrameCodec,
+        message: &str,
+    ) -> Result<FrameCodec, Error> {
+        let refusal = ServerMessage::AuthResult {
+            accepted: false,
+            message: message.to_string(),
+        };
+        write_frame(stream, codec, &refusal).await?;
+        Err(Error::new(ErrorKind::PermissionDenied, message.to_string()))
+    }
+
+    async fn authenticate(
+        &self,
+        stream: &mut TcpStream,
+        codec: &FrameCodec,
+    ) -> Result<FrameCodec, Error> {
+        let key = self.current_key();
+        let (name, client_challenge, client_public) = match self.read(stream, codec).await? {
+            ServerMessage::Auth {
+                name,
+                challenge,
+                public_key,
+            } => (name, challenge, public_key),
+            _ => return self.refuse(stream, codec, "expected an auth request").await,
+        };
+        if !self
+            .seen_challenges
+            .lock()
+            .unwrap()
+            .insert(client_challenge.clone())
+        {
+            return self.refuse(stream, codec, "replayed challenge").await;
+        }
+        let server_challenge = crytpo::generate_challenge();
+        let (ephemeral_secret, server_public) = crytpo::generate_ephemeral();
+        let identity_transcript = auth_transcript(
+            "identity",
+            &name,
+            &client_challenge,
+            &server_challenge,
+            &client_public,
+            &server_public,
+            &self.identity_key,
+        );
+        let identity_proof_res =
+            crytpo::prove_identity(&self.identity, &client_public, &identity_transcript);
+        if identity_proof_res.is_err() {
+            return self.refuse(stream, codec, "bad ephemeral key").await;
+        }
+        let transcript = auth_transcript(
+            "server",
+            &name,
+            &client_challenge,
+            &server_challenge,
+            &client_public,
+            &server_public,
+            &self.identity_key,
+        );
+        let challenge = ServerMessage::AuthChallenge {
+            challenge: server_challenge.clone(),
+            public_key: server_public.clone(),
+            identity_key: self.identity_key.clone(),
+            identity_proof: identity_proof_res.unwrap(),
+            proof: crytpo::prove(&transcript, &key)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+        };
+        write_frame(stream, codec, &challenge).await?;
+        let proof = match self.read(stream, codec).await? {
+            ServerMessage::AuthProof { proof } => proof,
+            _ => return self.refuse(stream, codec, "expected an auth proof").await,
+        };
+        let transcript = auth_transcript(
+            "client",
+            &name,
+            &client_challenge,
+            &server_challenge,
+            &client_public,
+            &server_public,
+            &self.identity_key,
+        );
+        if !crytpo::verify_proof(&proof, &transcript, &key) {
+            return self.refuse(stream, codec, "bad proof").await;
+        }
+        let keys_res = crytpo::derive_session_keys(
+            ephemeral_secret,
+            &client_public,
+            &session_salt(&client_challenge, &server_challenge),
+        );
+        if keys_res.is_none() {
+            return self.refuse(stream, codec, "bad ephemeral key").await;
+        }
+        let accepted = ServerMessage::AuthResult {
+            accepted: true,
+            message: format!("welcome {}", name),
+        };
+        write_frame(stream, codec, &accepted).await?;
+        print_success(&format!("mock server: {} authenticated", name));
+        let keys = keys_res.unwrap();
+        Ok(FrameCodec::session(
+            &keys.server_to_client,
+            &keys.client_to_server,
+        ))
+    }
+
+    async fn enroll(
+        &self,
+        stream: &mut TcpStream,
+        codec: &FrameCodec,
+        token_key: &[u8],
+    ) -> Result<(), Error> {
+        let (name, client_public) = match self.read(stream, codec).await? {
+            ServerMessage::Enroll { name, public_key } => (name, public_key),
+            _ => {
+                self.refuse(stream, codec, "expected an enrollment request")
+                    .await?;
+                return Ok(());
+            }
+        };
+        let (ephemeral_secret, server_public) = crytpo::generate_ephemeral();
+        let transcript =
+            enrollment_transcript(&name, &client_public, &server_public, &self.identity_key);
+        let identity_proof_res =
+            crytpo::prove_identity(&self.identity, &client_public, &transcript);
+        if identity_proof_res.is_err() {
+            self.refuse(stream, codec, "bad ephemeral key").await?;
+            return Ok(());
+        }
+        let accept = ServerMessage::EnrollAccept {
+            public_key: server_public.clone(),
+            identity_key: self.identity_key.clone(),
+            identity_proof: identity_proof_res.unwrap(),
+        };
+        write_frame(stream, codec, &accept).await?;
+        let keys_res = crytpo::derive_session_keys(
+            ephemeral_secret,
+            &client_public,
+            &enrollment_salt(token_key, &client_public, &server_public),
+        );
+        if keys_res.is_none() {
+            self.refuse(stream, codec, "bad ephemeral key").await?;
+            return Ok(());
+        }
+        let keys = keys_res.unwrap();
+        let session = FrameCodec::session(&keys.server_to_client, &keys.client_to_server);
+        let new_key = match self.read(stream, &session).await? {
+            ServerMessage::EnrollKey { key } => key,
+            _ => {
+                self.refuse(stream, &session, "expected a key").await?;
+                return Ok(());
+            }
+        };
+        if keys::validate_key(&new_key).is_err() {
+            self.refuse(stream, &session, "key is the wrong size")
+                .await?;
+            return Ok(());
+        }
+        *self.key.lock().unwrap() = new_key;
+        let result = ServerMessage::AuthResult {
+            accepted: true,
+            message: format!("{} enrolled", name),
+        };
+        write_frame(stream, &session, &result).await?;
+        print_success(&format!("mock server: {} enrolled", name));
+        Ok(())
+    }
+
+    fn answer(&self, id: u64, command: &str) -> ServerMessage {
+        if let Some(hex) = command.strip_prefix("rotate_key ") {
+            let key_res = keys::from_hex(hex);
+            if key_res.is_err() || keys::validate_key(key_res.as_ref().unwrap()).is_err() {
+                return ServerMessage::Error {
+                    id: Some(id),
+                    message: String::from("bad key"),
+                };
+            }
+            *self.key.lock().unwrap() = key_res.unwrap();
+            return ServerMessage::Response {
+                id,
+                content: String::from("key rotated"),
+            };
+        }
+        let content = match self.script.get(command) {
+            Some(scripted) => scripted.clone(),
+            None => format!("echo: {}", command),
+        };
+        ServerMessage::Response { id, content }
+    }
+
+    async fn session(&self, stream: TcpStream, codec: &FrameCodec) -> Result<(), Error> {
+        let (mut reader, writer) = stream.into_split();
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        for event in &self.events {
+            write_frame(&mut *writer.lock().await, codec, event).await?;
+        }
+        if let Some(interval) = self.event_interval
+            && !self.events.is_empty()
+        {
+            let events = self.events.clone();
+            let event_writer = writer.clone();
+            let event_codec = codec.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    for event in &events {
+                        let mut writer = event_writer.lock().await;
+                        if write_frame(&mut *writer, &event_codec, event)
+                            .await
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        loop {
+            let message = read_frame(&mut reader, codec).await?;
+            self.record(&message);
+            let hang_up = match &message {
+                ServerMessage::Command { command, .. } => self.hang_up_on.as_ref() == Some(command),
+                _ => false,
+            };
+            if hang_up {
+                return Ok(());
+            }
+            let reply = match message {
+                ServerMessage::Command { id, command } => self.answer(id, &command),
+                ServerMessage::Heartbeat { timestamp } => ServerMessage::Heartbeat { timestamp },
+                other => ServerMessage::Error {
+                    id: None,
+                    message: format!("mock server doesn't handle {:?}", other),
+                },
+            };
+            write_frame(&mut *writer.lock().await, codec, &reply).await?;
+        }
+    }
+}
@@ -1,17 +1,922 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio;
-use std::{io::Write, net::TcpStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Notify, oneshot, watch};
 
-use crate::print_error;
+use crate::crytpo;
+use crate::keys::{KeyRing, to_hex};
+use crate::protocol::{Destination, Message, ServerMessage, agree_version};
 
+// frames bigger than this are treated as garbage rather than allocated.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+const LEN_PREFIX: usize = 4;
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
-pub async fn send_to_server(input: String, address: String) -> Option<String>{
-    let connect_res = TcpStream::connect(address);
-    if connect_res.is_err(){
-        print_error("error connection to server", Some(connect_res.err().unwrap().to_string()));
-        return Some(String::from("failed to connect to server!"));
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// None once the connection has dropped, so late requests fail straight away
+// instead of sitting out their timeout.
+type PendingRequests = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<String, String>>>>>>;
+
+// clones share the sequence counter and replay window, so the writer and the
+// reader halves of a connection can each hold one.
+#[derive(Clone)]
+pub struct FrameCodec {
+    send_key: Vec<u8>,
+    recv_key: Vec<u8>,
+    send_sequence: Arc<AtomicU64>,
+    recv_window: Arc<Mutex<crytpo::ReplayWindow>>,
+}
+
+impl FrameCodec {
+    // the handshake codecs for each end of a connection sealed with a shared
+    // key. the two ends send under different keys derived from it.
+    pub fn client(key: &[u8]) -> Self {
+        let keys = crytpo::derive_handshake_keys(key);
+        Self::session(&keys.client_to_server, &keys.server_to_client)
+    }
+
+    pub fn server(key: &[u8]) -> Self {
+        let keys = crytpo::derive_handshake_keys(key);
+        Self::session(&keys.server_to_client, &keys.client_to_server)
+    }
+
+    // once the handshake is done each direction gets its own ephemeral key.
+    pub fn session(send_key: &[u8], recv_key: &[u8]) -> Self {
+        Self {
+            send_key: send_key.to_vec(),
+            recv_key: recv_key.to_vec(),
+            send_sequence: Arc::new(AtomicU64::new(0)),
+            recv_window: Arc::new(Mutex::new(crytpo::ReplayWindow::default())),
+        }
+    }
+
+    // seals the message with the send key and prefixes it with its length
+    // as a big endian u32 so the reader knows where the frame ends.
+    pub fn encode(&self, message: &ServerMessage) -> Result<Vec<u8>, Error> {
+        let sequence = self.send_sequence.fetch_add(1, Ordering::SeqCst);
+        let json = message
+            .to_json()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let sealed = crytpo::encrypt_sequenced(&json, &self.send_key, sequence)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let mut frame = Vec::with_capacity(LEN_PREFIX + sealed.len());
+        frame.extend_from_slice(&(sealed.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&sealed);
+        Ok(frame)
+    }
+
+    // pulls one complete frame off the front of the buffer if there is one.
+    // partial frames are left in place until more bytes arrive.
+    pub fn decode(&self, buffer: &mut Vec<u8>) -> Result<Option<ServerMessage>, Error> {
+        if buffer.len() < LEN_PREFIX {
+            return Ok(None);
+        }
+        let frame_len = frame_length(&buffer[..LEN_PREFIX])?;
+        if buffer.len() < LEN_PREFIX + frame_len {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = buffer.drain(..LEN_PREFIX + frame_len).collect();
+        Ok(Some(self.open(&frame[LEN_PREFIX..])?))
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<ServerMessage, Error> {
+        let json_res = {
+            let mut window = self.recv_window.lock().unwrap();
+            crytpo::decrypt_sequenced(sealed, &self.recv_key, &mut window)
+        };
+        if json_res.is_err() {
+            return Err(Error::new(ErrorKind::InvalidData, json_res.err().unwrap()));
+        }
+        ServerMessage::from_json(&json_res.unwrap())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+fn frame_length(prefix: &[u8]) -> Result<usize, Error> {
+    let frame_len = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+    if frame_len > MAX_FRAME_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes is larger than the maximum allowed",
+                frame_len
+            ),
+        ));
+    }
+    Ok(frame_len)
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    codec: &FrameCodec,
+    message: &ServerMessage,
+) -> Result<(), Error> {
+    let frame = codec.encode(message)?;
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+// reads one frame without opening it, for when we don't know yet which key
+// it was sealed with.
+pub async fn read_sealed_frame<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Vec<u8>, Error> {
+    let mut prefix = [0u8; LEN_PREFIX];
+    stream.read_exact(&mut prefix).await?;
+    let frame_len = frame_length(&prefix)?;
+    let mut sealed = vec![0u8; frame_len];
+    stream.read_exact(&mut sealed).await?;
+    Ok(sealed)
+}
+
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    codec: &FrameCodec,
+) -> Result<ServerMessage, Error> {
+    let sealed = read_sealed_frame(stream).await?;
+    codec.open(&sealed)
+}
+
+// both sides open with a hello and settle on a version with agree_version.
+async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    codec: &FrameCodec,
+) -> Result<(u16, Vec<String>), Error> {
+    write_frame(stream, codec, &ServerMessage::hello()).await?;
+    let hello_res = tokio::time::timeout(HELLO_TIMEOUT, read_frame(stream, codec)).await;
+    if hello_res.is_err() {
+        return Err(Error::new(
+            ErrorKind::TimedOut,
+            "server never answered our hello",
+        ));
+    }
+    let hello = hello_res.unwrap();
+    if hello
+        .as_ref()
+        .is_err_and(|error| error.kind() == ErrorKind::UnexpectedEof)
+    {
+        // a server that can't open our hello has no way to answer it, so it
+        // just hangs up. treat that as a refusal so a fallback key gets tried.
+        return Err(auth_error(String::from(
+            "server hung up on our hello, it probably doesn't know our key",
+        )));
+    }
+    match hello? {
+        ServerMessage::Hello {
+            version,
+            min_version,
+            capabilities,
+        } => {
+            let agreed = agree_version(version, min_version)
+                .map_err(|e| Error::new(ErrorKind::Unsupported, e))?;
+            Ok((agreed, capabilities))
+        }
+        other => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("expected a hello from the server, got {:?}", other),
+        )),
+    }
+}
+
+// what each side has to prove it can seal. both challenges are fresh per
+// connection and the role is baked in, so a proof can't be replayed later or
+// bounced back at the side that made it. the ephemeral public keys are in
+// there too, which is what ties the static key to this session's exchange,
+// along with the server identity we pin at enrollment.
+pub fn auth_transcript(
+    role: &str,
+    name: &str,
+    client_challenge: &[u8],
+    server_challenge: &[u8],
+    client_public: &[u8],
+    server_public: &[u8],
+    identity_key: &[u8],
+) -> String {
+    format!(
+        "tetanus-auth|{}|{}|{}|{}|{}|{}|{}",
+        role,
+        name,
+        to_hex(client_challenge),
+        to_hex(server_challenge),
+        to_hex(client_public),
+        to_hex(server_public),
+        to_hex(identity_key)
+    )
+}
+
+// what the server seals to prove it owns the identity key it handed us
+// during enrollment. our ephemeral key is in there so the proof is only good
+// for this one exchange.
+pub fn enrollment_transcript(
+    name: &str,
+    client_public: &[u8],
+    server_public: &[u8],
+    identity_key: &[u8],
+) -> String {
+    format!(
+        "tetanus-enroll|{}|{}|{}|{}",
+        name,
+        to_hex(client_public),
+        to_hex(server_public),
+        to_hex(identity_key)
+    )
+}
+
+pub fn enrollment_salt(token_key: &[u8], client_public: &[u8], server_public: &[u8]) -> Vec<u8> {
+    [token_key, client_public, server_public].concat()
+}
+
+pub fn session_salt(client_challenge: &[u8], server_challenge: &[u8]) -> Vec<u8> {
+    [client_challenge, server_challenge].concat()
+}
+
+fn auth_error(message: String) -> Error {
+    Error::new(ErrorKind::PermissionDenied, message)
+}
+
+// proves who we are with the static key and swaps ephemeral x25519 keys in
+// the same breath. what comes back is the codec for the rest of the session,
+// which never touches the static key again.
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    static_key: &[u8],
+    name: &str,
+    pinned_identity: Option<&[u8]>,
+) -> Result<FrameCodec, Error> {
+    let client_challenge = crytpo::generate_challenge();
+    let (ephemeral_secret, client_public) = crytpo::generate_ephemeral();
+    let auth = ServerMessage::Auth {
+        name: name.to_string(),
+        challenge: client_challenge.clone(),
+        public_key: client_public.clone(),
+    };
+    write_frame(stream, codec, &auth).await?;
+    let challenge_res = tokio::time::timeout(HELLO_TIMEOUT, read_frame(stream, codec)).await;
+    if challenge_res.is_err() {
+        return Err(auth_error(String::from(
+            "server never answered our authentication request",
+        )));
+    }
+    let (server_challenge, server_public, identity) = match challenge_res.unwrap()? {
+        ServerMessage::AuthChallenge {
+            challenge,
+            public_key,
+            identity_key,
+            identity_proof,
+            proof,
+        } => {
+            if pinned_identity.is_some_and(|pinned| pinned != identity_key.as_slice()) {
+                return Err(auth_error(format!(
+                    "server identity {} does not match the one pinned at enrollment, refusing to talk to it",
+                    to_hex(&identity_key)
+                )));
+            }
+            // anyone can claim the pinned key, only its owner can seal
+            // this under the secret we share with it.
+            let identity_transcript = auth_transcript(
+                "identity",
+                name,
+                &client_challenge,
+                &challenge,
+                &client_public,
+                &public_key,
+                &identity_key,
+            );
+            if !crytpo::verify_identity(
+                &ephemeral_secret,
+                &identity_key,
+                &identity_proof,
+                &identity_transcript,
+            ) {
+                return Err(auth_error(format!(
+                    "server failed to prove it owns identity {}, refusing to talk to it",
+                    to_hex(&identity_key)
+                )));
+            }
+            let transcript = auth_transcript(
+                "server",
+                name,
+                &client_challenge,
+                &challenge,
+                &client_public,
+                &public_key,
+                &identity_key,
+            );
+            if !crytpo::verify_proof(&proof, &transcript, static_key) {
+                return Err(auth_error(String::from(
+                    "server failed to prove it holds our key, refusing to talk to it",
+                )));
+            }
+            (challenge, public_key, identity_key)
+        }
+        ServerMessage::AuthResult { message, .. } => {
+            return Err(auth_error(format!("server refused us: {}", message)));
+        }
+        other => {
+            return Err(auth_error(format!(
+                "expected an authentication challenge, got {:?}",
+                other
+            )));
+        }
+    };
+    let transcript = auth_transcript(
+        "client",
+        name,
+        &client_challenge,
+        &server_challenge,
+        &client_public,
+        &server_public,
+        &identity,
+    );
+    let proof = ServerMessage::AuthProof {
+        proof: crytpo::prove(&transcript, static_key)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+    };
+    write_frame(stream, codec, &proof).await?;
+    let result_res = tokio::time::timeout(HELLO_TIMEOUT, read_frame(stream, codec)).await;
+    if result_res.is_err() {
+        return Err(auth_error(String::from(
+            "server never told us if we were accepted",
+        )));
+    }
+    match result_res.unwrap()? {
+        ServerMessage::AuthResult { accepted: true, .. } => {}
+        ServerMessage::AuthResult { message, .. } => {
+            return Err(auth_error(format!("server refused us: {}", message)));
+        }
+        other => {
+            return Err(auth_error(format!(
+                "expected an authentication result, got {:?}",
+                other
+            )));
+        }
+    }
+    let salt = session_salt(&client_challenge, &server_challenge);
+    let keys_res = crytpo::derive_session_keys(ephemeral_secret, &server_public, &salt);
+    if keys_res.is_none() {
+        return Err(auth_error(String::from(
+            "server sent a bad ephemeral key, refusing to talk to it",
+        )));
+    }
+    let keys = keys_res.unwrap();
+    Ok(FrameCodec::session(
+        &keys.client_to_server,
+        &keys.server_to_client,
+    ))
+}
+
+// registers our name and key with the server using a one-time token. the
+// token only protects the handshake, the key itself goes over a fresh x25519
+// session. gives back the server identity key so it can be pinned.
+pub async fn enroll(address: &str, name: &str, token: &str, key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut stream = TcpStream::connect(address).await?;
+    let token_key = crytpo::derive_enrollment_key(token);
+    let handshake_codec = FrameCodec::client(&token_key);
+    negotiate(&mut stream, &handshake_codec).await?;
+    let (ephemeral_secret, client_public) = crytpo::generate_ephemeral();
+    let request = ServerMessage::Enroll {
+        name: name.to_string(),
+        public_key: client_public.clone(),
+    };
+    write_frame(&mut stream, &handshake_codec, &request).await?;
+    let accept_res =
+        tokio::time::timeout(HELLO_TIMEOUT, read_frame(&mut stream, &handshake_codec)).await;
+    if accept_res.is_err() {
+        return Err(auth_error(String::from(
+            "server never answered our enrollment request",
+        )));
+    }
+    let (server_public, identity) = match accept_res.unwrap()? {
+        ServerMessage::EnrollAccept {
+            public_key,
+            identity_key,
+            identity_proof,
+        } => {
+            let transcript =
+                enrollment_transcript(name, &client_public, &public_key, &identity_key);
+            if !crytpo::verify_identity(
+                &ephemeral_secret,
+                &identity_key,
+                &identity_proof,
+                &transcript,
+            ) {
+                return Err(auth_error(format!(
+                    "server failed to prove it owns identity {}, refusing to enroll",
+                    to_hex(&identity_key)
+                )));
+            }
+            (public_key, identity_key)
+        }
+        ServerMessage::AuthResult { message, .. } => {
+            return Err(auth_error(format!(
+                "server refused enrollment: {}",
+                message
+            )));
+        }
+        other => {
+            return Err(auth_error(format!(
+                "expected an enrollment answer, got {:?}",
+                other
+            )));
+        }
+    };
+    let salt = enrollment_salt(&token_key, &client_public, &server_public);
+    let keys_res = crytpo::derive_session_keys(ephemeral_secret, &server_public, &salt);
+    if keys_res.is_none() {
+        return Err(auth_error(String::from(
+            "server sent a bad ephemeral key, refusing to enroll",
+        )));
+    }
+    let keys = keys_res.unwrap();
+    let codec = FrameCodec::session(&keys.client_to_server, &keys.server_to_client);
+    let key_message = ServerMessage::EnrollKey { key: key.to_vec() };
+    write_frame(&mut stream, &codec, &key_message).await?;
+    let result_res = tokio::time::timeout(HELLO_TIMEOUT, read_frame(&mut stream, &codec)).await;
+    if result_res.is_err() {
+        return Err(auth_error(String::from(
+            "server never told us if enrollment worked",
+        )));
+    }
+    match result_res.unwrap()? {
+        ServerMessage::AuthResult { accepted: true, .. } => Ok(identity),
+        ServerMessage::AuthResult { message, .. } => Err(auth_error(format!(
+            "server refused enrollment: {}",
+            message
+        ))),
+        other => Err(auth_error(format!(
+            "expected an enrollment result, got {:?}",
+            other
+        ))),
+    }
+}
+
+// one connection to the server that can have many requests in flight at
+// once. the reader task hands each response to whoever is waiting on its id.
+pub struct ServerSession {
+    pub version: u16,
+    pub capabilities: Vec<String>,
+    writer: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+    codec: FrameCodec,
+    next_id: AtomicU64,
+    pending: PendingRequests,
+    closed: Arc<Notify>,
+}
+
+impl ServerSession {
+    pub async fn connect(
+        address: &str,
+        name: &str,
+        key: &[u8],
+        pinned_identity: Option<&[u8]>,
+        console_tx: Sender<Message>,
+    ) -> Result<Self, Error> {
+        let mut stream = TcpStream::connect(address).await?;
+        let handshake_codec = FrameCodec::client(key);
+        let (version, capabilities) = negotiate(&mut stream, &handshake_codec).await?;
+        let codec = authenticate(&mut stream, &handshake_codec, key, name, pinned_identity).await?;
+        let (reader, writer) = stream.into_split();
+        let pending: PendingRequests = Arc::new(Mutex::new(Some(HashMap::new())));
+        let closed = Arc::new(Notify::new());
+        tokio::spawn(read_responses(
+            reader,
+            codec.clone(),
+            pending.clone(),
+            console_tx,
+            closed.clone(),
+        ));
+        Ok(Self {
+            version,
+            capabilities,
+            writer: Arc::new(tokio::sync::Mutex::new(writer)),
+            codec,
+            next_id: AtomicU64::new(1),
+            pending,
+            closed,
+        })
+    }
+
+    fn forget(&self, id: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_some() {
+            pending.as_mut().unwrap().remove(&id);
+        }
+    }
+
+    // resolves once the reader task has seen the connection drop.
+    pub async fn closed(&self) {
+        self.closed.notified().await;
+    }
+
+    pub async fn request(&self, input: &str, timeout: Duration) -> Result<String, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (response_tx, response_rx) = oneshot::channel();
+        let registered = match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, response_tx).is_none(),
+            None => false,
+        };
+        if !registered {
+            return Err(format!(
+                "error! connection closed before request {} was sent",
+                id
+            ));
+        }
+        let command = ServerMessage::Command {
+            id,
+            command: input.to_string(),
+        };
+        let write_res = {
+            let mut writer = self.writer.lock().await;
+            write_frame(&mut *writer, &self.codec, &command).await
+        };
+        if write_res.is_err() {
+            self.forget(id);
+            return Err(format!(
+                "error sending request {} to server!\n{}",
+                id,
+                write_res.err().unwrap()
+            ));
+        }
+        match tokio::time::timeout(timeout, response_rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(format!(
+                "error! connection closed before request {} was answered",
+                id
+            )),
+            Err(_) => {
+                self.forget(id);
+                Err(format!("error! request {} timed out", id))
+            }
+        }
+    }
+}
+
+async fn read_responses(
+    mut reader: OwnedReadHalf,
+    codec: FrameCodec,
+    pending: PendingRequests,
+    console_tx: Sender<Message>,
+    closed: Arc<Notify>,
+) {
+    loop {
+        let frame_res = read_frame(&mut reader, &codec).await;
+        if frame_res.is_err() {
+            let error = frame_res.err().unwrap();
+            if error.kind() == ErrorKind::InvalidData {
+                let _ = console_tx
+                    .send(Message {
+                        source: Destination::Console,
+                        destination: Destination::Console,
+                        payload: None,
+                        content: format!("error! dropping the server connection\n{}", error),
+                    })
+                    .await;
+            }
+            // dropping the waiting senders lets every in-flight request fail fast.
+            pending.lock().unwrap().take();
+            closed.notify_one();
+            return;
+        }
+        let frame = frame_res.unwrap();
+        let answer = match &frame {
+            ServerMessage::Response { id, content } => Some((*id, Ok(content.clone()))),
+            ServerMessage::Error {
+                id: Some(id),
+                message,
+            } => Some((*id, Err(format!("error from server! {}", message)))),
+            ServerMessage::Heartbeat { .. } => continue,
+            _ => None,
+        };
+        if let Some((id, response)) = answer {
+            let waiting = pending
+                .lock()
+                .unwrap()
+                .as_mut()
+                .and_then(|waiting| waiting.remove(&id));
+            if let Some(waiting) = waiting {
+                let _ = waiting.send(response);
+                continue;
+            }
+        }
+        let message = Message {
+            source: Destination::Server,
+            destination: Destination::Console,
+            content: frame.describe(),
+            payload: Some(frame),
+        };
+        if console_tx.send(message).await.is_err() {
+            pending.lock().unwrap().take();
+            closed.notify_one();
+            return;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting,
+    Down,
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Connected => "connected",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Down => "down",
+        }
+    }
+}
+
+// a command headed for the server. if nobody is waiting on the response it
+// gets printed to the console like any other server message.
+pub struct ServerRequest {
+    pub input: String,
+    pub response_tx: Option<oneshot::Sender<Result<String, String>>>,
+}
+
+impl ServerRequest {
+    pub fn new(input: String) -> Self {
+        Self {
+            input,
+            response_tx: None,
+        }
+    }
+}
+
+// owns the single session to the server for the life of the client. runs
+// until every request sender has been dropped.
+pub async fn connection_manager(
+    address: String,
+    name: String,
+    keyring: KeyRing,
+    pinned_identity: Option<Vec<u8>>,
+    mut request_rx: Receiver<ServerRequest>,
+    console_tx: Sender<Message>,
+    state_tx: watch::Sender<ConnectionState>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let mut connect_res = Err(Error::new(ErrorKind::NotFound, "no key to connect with"));
+        // during a rotation grace period the server may still only know the
+        // old key, so fall back to it if the new one is refused.
+        for key in keyring.candidates() {
+            connect_res = ServerSession::connect(
+                &address,
+                &name,
+                &key,
+                pinned_identity.as_deref(),
+                console_tx.clone(),
+            )
+            .await;
+            if connect_res.is_ok()
+                || connect_res.as_ref().err().unwrap().kind() != ErrorKind::PermissionDenied
+            {
+                break;
+            }
+        }
+        if connect_res.is_err() {
+            let error = connect_res.err().unwrap();
+            if error.kind() == ErrorKind::PermissionDenied {
+                let _ = console_tx
+                    .send(Message {
+                        source: Destination::Console,
+                        destination: Destination::Console,
+                        payload: None,
+                        content: format!("error authenticating with the server!\n{}", error),
+                    })
+                    .await;
+            }
+            let _ = state_tx.send(ConnectionState::Reconnecting);
+            let wait = tokio::time::sleep(backoff);
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    request = request_rx.recv() => {
+                        if request.is_none() {
+                            let _ = state_tx.send(ConnectionState::Down);
+                            return;
+                        }
+                        reject_request(request.unwrap(), &console_tx).await;
+                    }
+                }
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            continue;
+        }
+        let session = Arc::new(connect_res.unwrap());
+        backoff = MIN_BACKOFF;
+        let _ = state_tx.send(ConnectionState::Connected);
+        loop {
+            tokio::select! {
+                _ = session.closed() => {
+                    let _ = state_tx.send(ConnectionState::Reconnecting);
+                    break;
+                }
+                request = request_rx.recv() => {
+                    if request.is_none() {
+                        let _ = state_tx.send(ConnectionState::Down);
+                        return;
+                    }
+                    tokio::spawn(forward_request(
+                        session.clone(),
+                        request.unwrap(),
+                        console_tx.clone(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+async fn forward_request(
+    session: Arc<ServerSession>,
+    request: ServerRequest,
+    console_tx: Sender<Message>,
+) {
+    let response_res = session.request(&request.input, REQUEST_TIMEOUT).await;
+    if let Some(response_tx) = request.response_tx {
+        let _ = response_tx.send(response_res);
+        return;
+    }
+    let content = match response_res {
+        Ok(response) => response,
+        Err(error) => error,
+    };
+    let _ = console_tx
+        .send(Message {
+            source: Destination::Server,
+            destination: Destination::Console,
+            payload: None,
+            content,
+        })
+        .await;
+}
+
+async fn reject_request(request: ServerRequest, console_tx: &Sender<Message>) {
+    let error = String::from("error! not connected to the server, try again once it reconnects");
+    if let Some(response_tx) = request.response_tx {
+        let _ = response_tx.send(Err(error));
+        return;
+    }
+    let _ = console_tx
+        .send(Message {
+            source: Destination::Console,
+            destination: Destination::Console,
+            payload: None,
+            content: error,
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(id: u64, text: &str) -> ServerMessage {
+        ServerMessage::Command {
+            id,
+            command: text.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip_over_a_stream() {
+        let key = crytpo::generate_key();
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let sender = FrameCodec::client(&key);
+        let receiver = FrameCodec::server(&key);
+        let messages = vec![
+            command(1, "ping"),
+            command(2, &"x".repeat(1000)),
+            ServerMessage::hello(),
+        ];
+        let expected = messages.clone();
+        // the duplex buffer is smaller than the frames, so the writer has to
+        // run alongside the reader.
+        let writer = tokio::spawn(async move {
+            for message in &messages {
+                write_frame(&mut client, &sender, message).await.unwrap();
+            }
+        });
+        for message in expected {
+            assert_eq!(read_frame(&mut server, &receiver).await.unwrap(), message);
+        }
+        writer.await.unwrap();
+    }
+
+    #[test]
+    fn decode_waits_for_the_whole_frame() {
+        let key = crytpo::generate_key();
+        let sender = FrameCodec::client(&key);
+        let codec = FrameCodec::server(&key);
+        let mut frames = sender.encode(&command(1, "first")).unwrap();
+        frames.extend(sender.encode(&command(2, "second")).unwrap());
+
+        let mut buffer = frames[..3].to_vec();
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        buffer = frames[..frames.len() - 1].to_vec();
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap(),
+            Some(command(1, "first"))
+        );
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        buffer.push(*frames.last().unwrap());
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap(),
+            Some(command(2, "second"))
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_is_an_eof() {
+        let key = crytpo::generate_key();
+        let codec = FrameCodec::server(&key);
+        let frame = FrameCodec::client(&key)
+            .encode(&command(1, "ping"))
+            .unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&frame[..frame.len() - 5]).await.unwrap();
+        drop(client);
+        let error = read_frame(&mut server, &codec).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+
+        // cut off inside the length prefix too.
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&frame[..2]).await.unwrap();
+        drop(client);
+        let error = read_frame(&mut server, &codec).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn oversize_length_prefix_is_refused() {
+        let key = crytpo::generate_key();
+        let codec = FrameCodec::server(&key);
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(&((MAX_FRAME_LEN + 1) as u32).to_be_bytes())
+            .await
+            .unwrap();
+        let error = read_frame(&mut server, &codec).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let mut buffer = u32::MAX.to_be_bytes().to_vec();
+        let error = codec.decode(&mut buffer).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn garbage_and_replayed_frames_are_rejected() {
+        let key = crytpo::generate_key();
+        let sender = FrameCodec::client(&key);
+        let receiver = FrameCodec::server(&key);
+        let frame = sender.encode(&command(1, "ping")).unwrap();
+
+        let mut tampered = frame.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&tampered).await.unwrap();
+        client.write_all(&frame).await.unwrap();
+        client.write_all(&frame).await.unwrap();
+        let error = read_frame(&mut server, &receiver).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            read_frame(&mut server, &receiver).await.unwrap(),
+            command(1, "ping")
+        );
+        let error = read_frame(&mut server, &receiver).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn hello_is_not_accepted_back_by_its_sender() {
+        let key = crytpo::generate_key();
+        let client = FrameCodec::client(&key);
+        let server = FrameCodec::server(&key);
+        let (mut near, mut far) = tokio::io::duplex(1024);
+        write_frame(&mut near, &client, &ServerMessage::hello())
+            .await
+            .unwrap();
+        let sealed = read_sealed_frame(&mut far).await.unwrap();
+        // a reflected hello must not open under the client's receive key.
+        assert_eq!(
+            client.open(&sealed).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(server.open(&sealed).unwrap(), ServerMessage::hello());
     }
-    let mut stream = connect_res.unwrap();
-    let server_send_line = format!("1|||command|||0|||{}", input);
-    stream.write(server_send_line.as_bytes()).unwrap();
-    return None;
-}
\ No newline at end of file
+}
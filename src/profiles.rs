@@ -0,0 +1,377 @@
+use std::fs::{copy, create_dir_all, read_dir, read_link, remove_dir_all};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::settings::Settings;
+use crate::table::Table;
+use crate::{crytpo, keys, templates};
+
+pub const DEFAULT_PROFILE: &str = "main_attacker";
+pub const CONFIG_FILE: &str = "config.conf";
+
+// profiles live side by side, clients/<name>/config.conf.
+pub fn clients_dir(config: &Path) -> PathBuf {
+    let mut clients = config.to_path_buf();
+    clients.pop();
+    clients.pop();
+    clients
+}
+
+pub fn profile_name(config: &Path) -> String {
+    config
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or(String::from(DEFAULT_PROFILE))
+}
+
+pub fn profile_config(clients: &Path, name: &str) -> PathBuf {
+    clients.join(name).join(CONFIG_FILE)
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(format!("{} isn't a usable profile name", name));
+    }
+    Ok(())
+}
+
+pub fn list(config: &Path) -> Result<String, String> {
+    let clients = clients_dir(config);
+    let dir_res = read_dir(&clients);
+    if dir_res.is_err() {
+        return Err(format!(
+            "error reading {}!\n{}",
+            clients.display(),
+            dir_res.err().unwrap()
+        ));
+    }
+    let active = profile_name(config);
+    let mut names: Vec<String> = dir_res
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join(CONFIG_FILE).exists())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    let mut lines = vec![String::from("profile|active|server|key")];
+    for name in names {
+        let settings_res = Settings::peek(&profile_config(&clients, &name))
+            .and_then(|settings| settings.validate().map(|_| settings));
+        let mut marker = "";
+        if name == active {
+            marker = "*";
+        }
+        if settings_res.is_err() {
+            lines.push(format!("{}|{}|broken config|", name, marker));
+            continue;
+        }
+        let settings = settings_res.unwrap();
+        let mut key = String::from("missing");
+        if let Ok(contents) = std::fs::read(&settings.key_file) {
+            if keys::is_protected(&contents) {
+                key = String::from("passphrase protected");
+            } else {
+                key = keys::fingerprint(&contents);
+            }
+        }
+        lines.push(format!(
+            "{}|{}|{}|{}",
+            name, marker, settings.server_address, key
+        ));
+    }
+    let mut table = Table::default();
+    table.build(lines);
+    Ok(table.get_table())
+}
+
+// project folders for another profile sit next to this one's, with the
+// profile name tacked on. /home/pyro/projects/current becomes
+// /home/pyro/projects/current_<profile>.
+pub fn profile_folder(folder: &Path, name: &str) -> PathBuf {
+    let leaf = folder
+        .file_name()
+        .map(|leaf| leaf.to_string_lossy().to_string())
+        .unwrap_or_default();
+    folder.with_file_name(format!("{}_{}", leaf, name))
+}
+
+// points the project folders at ones of the profile's own and hands back
+// everything that needs creating. tools are shared.
+fn own_folders(settings: &mut Settings, new_dir: &Path, name: &str) -> Vec<PathBuf> {
+    settings.key_file = new_dir.join("key");
+    let mut dirs = vec![new_dir.join("projects")];
+    for folder in [
+        &mut settings.current_files,
+        &mut settings.current_notes,
+        &mut settings.upcoming_files,
+        &mut settings.upcoming_notes,
+        &mut settings.archive,
+    ] {
+        if let Some(current) = folder.as_ref() {
+            let derived = profile_folder(current, name);
+            dirs.push(derived.clone());
+            *folder = Some(derived);
+        }
+    }
+    dirs
+}
+
+fn create_dirs(dirs: &[PathBuf]) -> Result<(), String> {
+    for dir in dirs {
+        create_dir_all(dir).map_err(|e| format!("error creating {}!\n{}", dir.display(), e))?;
+    }
+    Ok(())
+}
+
+// a new profile starts from the current one's settings, with its own key,
+// an empty projects folder and a fresh set of note templates. its project
+// folders get derived from the current ones so the two profiles never see
+// each other's projects, tools are shared.
+pub fn create(config: &Path, name: &str) -> Result<String, String> {
+    check_name(name)?;
+    let clients = clients_dir(config);
+    let new_config = profile_config(&clients, name);
+    let new_dir = new_config.parent().unwrap().to_path_buf();
+    if new_dir.exists() {
+        return Err(format!("profile {} already exists", name));
+    }
+    let mut settings = Settings::load(config)?;
+    let mut dirs = own_folders(&mut settings, &new_dir, name);
+    dirs.push(templates::templates_dir(&new_config));
+    create_dirs(&dirs)?;
+    templates::upgrade(&templates::templates_dir(&new_config))?;
+    keys::save_key(&settings.key_file, &crytpo::generate_key(), "")?;
+    settings.save(&new_config)?;
+    Ok(format!(
+        "created profile {}, use it with --name {}. its project folders end in _{}. it has a new key without a passphrase, set one with --name {} --change-passphrase and enroll it with --enroll",
+        name, name, name, name
+    ))
+}
+
+// like create, but starting from another profile's settings and note
+// templates. the key, pinned server and projects stay with the original,
+// the copy gets its own of each and has to be enrolled.
+pub fn copy_profile(config: &Path, from: &str, to: &str) -> Result<String, String> {
+    check_name(from)?;
+    check_name(to)?;
+    let clients = clients_dir(config);
+    let from_config = profile_config(&clients, from);
+    let to_config = profile_config(&clients, to);
+    let to_dir = to_config.parent().unwrap().to_path_buf();
+    if !from_config.exists() {
+        return Err(format!("no profile named {}", from));
+    }
+    if to_dir.exists() {
+        return Err(format!("profile {} already exists", to));
+    }
+    let mut settings = Settings::peek(&from_config)?;
+    let dirs = own_folders(&mut settings, &to_dir, to);
+    let copy_res = fill_copy(&from_config, &to_config, &settings, &dirs);
+    if copy_res.is_err() {
+        let _ = remove_dir_all(&to_dir);
+        return Err(copy_res.err().unwrap());
+    }
+    Ok(format!(
+        "copied profile {} to {}. its project folders end in _{}. it has a new key without a passphrase, enroll it with --name {} --enroll",
+        from, to, to, to
+    ))
+}
+
+// everything after the checks, so copy_profile can clear away a half made
+// copy when any of it fails.
+fn fill_copy(
+    from_config: &Path,
+    to_config: &Path,
+    settings: &Settings,
+    dirs: &[PathBuf],
+) -> Result<(), String> {
+    create_dirs(dirs)?;
+    let from_templates = templates::templates_dir(from_config);
+    let to_templates = templates::templates_dir(to_config);
+    if from_templates.is_dir() {
+        copy_dir(&from_templates, &to_templates)?;
+    } else {
+        create_dirs(std::slice::from_ref(&to_templates))?;
+        templates::upgrade(&to_templates)?;
+    }
+    keys::save_key(&settings.key_file, &crytpo::generate_key(), "")?;
+    settings.save(to_config)
+}
+
+pub fn delete(config: &Path, name: &str, confirmed: bool) -> Result<String, String> {
+    check_name(name)?;
+    if name == profile_name(config) {
+        return Err(String::from(
+            "can't delete the profile that's in use, start with another --name first",
+        ));
+    }
+    let clients = clients_dir(config);
+    let target_config = profile_config(&clients, name);
+    let target_dir = target_config.parent().unwrap().to_path_buf();
+    if !target_config.exists() {
+        return Err(format!("no profile named {}", name));
+    }
+    if !confirmed {
+        return Ok(format!(
+            "this deletes {} including its key and project configs.\nrun profile delete {} yes to go through with it",
+            target_dir.display(),
+            name
+        ));
+    }
+    let remove_res = remove_dir_all(&target_dir);
+    if remove_res.is_err() {
+        return Err(format!(
+            "error deleting {}!\n{}",
+            target_dir.display(),
+            remove_res.err().unwrap()
+        ));
+    }
+    Ok(format!("deleted profile {}", name))
+}
+
+pub fn copy_dir(from: &Path, to: &Path) -> Result<(), String> {
+    for entry_res in WalkDir::new(from) {
+        if entry_res.is_err() {
+            return Err(format!(
+                "error walking {}!\n{}",
+                from.display(),
+                entry_res.err().unwrap()
+            ));
+        }
+        let entry = entry_res.unwrap();
+        let target = to.join(entry.path().strip_prefix(from).unwrap());
+        if entry.file_type().is_dir() {
+            let create_res = create_dir_all(&target);
+            if create_res.is_err() {
+                return Err(format!(
+                    "error creating {}!\n{}",
+                    target.display(),
+                    create_res.err().unwrap()
+                ));
+            }
+        } else if entry.file_type().is_symlink() {
+            // copied as the link itself, following it could drag in anything.
+            let link_res = read_link(entry.path()).and_then(|link| symlink(link, &target));
+            if link_res.is_err() {
+                return Err(format!(
+                    "error copying link {}!\n{}",
+                    entry.path().display(),
+                    link_res.err().unwrap()
+                ));
+            }
+        } else {
+            let copy_res = copy(entry.path(), &target);
+            if copy_res.is_err() {
+                return Err(format!(
+                    "error copying {}!\n{}",
+                    entry.path().display(),
+                    copy_res.err().unwrap()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{read, read_to_string, write};
+
+    fn main_profile(dir: &Path) -> (PathBuf, Settings) {
+        let config = profile_config(&dir.join("clients"), DEFAULT_PROFILE);
+        create_dir_all(config.parent().unwrap()).unwrap();
+        let mut settings = Settings::for_config(&config);
+        settings.current_files = Some(dir.join("projects/current"));
+        settings.current_notes = Some(dir.join("notes/current"));
+        settings.upcoming_files = Some(dir.join("projects/upcoming"));
+        settings.upcoming_notes = Some(dir.join("notes/upcoming"));
+        settings.tools = Some(dir.join("tools"));
+        settings.save(&config).unwrap();
+        (config, settings)
+    }
+
+    #[test]
+    fn create_gives_the_profile_its_own_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, settings) = main_profile(dir.path());
+
+        create(&config, "redteam").unwrap();
+        let new_config = profile_config(&clients_dir(&config), "redteam");
+        let created = Settings::load(&new_config).unwrap();
+        assert_eq!(
+            created.current_files,
+            Some(dir.path().join("projects/current_redteam"))
+        );
+        assert_eq!(
+            created.upcoming_notes,
+            Some(dir.path().join("notes/upcoming_redteam"))
+        );
+        assert!(dir.path().join("projects/current_redteam").is_dir());
+        assert_eq!(created.archive, None);
+        assert_eq!(created.tools, settings.tools);
+        assert_eq!(created.key_file, new_config.parent().unwrap().join("key"));
+        assert!(created.key_file.exists());
+        assert!(create(&config, "redteam").is_err());
+    }
+
+    #[test]
+    fn copy_gets_its_own_key_and_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, settings) = main_profile(dir.path());
+        let from_dir = config.parent().unwrap().to_path_buf();
+        keys::save_key(&settings.key_file, &crytpo::generate_key(), "").unwrap();
+        keys::save_server_identity(&config, &[7; 32]).unwrap();
+        create_dir_all(from_dir.join("projects")).unwrap();
+        write(from_dir.join("projects/acme.conf"), "name = \"acme\"\n").unwrap();
+        let templates = templates::templates_dir(&config);
+        create_dir_all(&templates).unwrap();
+        write(templates.join("internal.md"), "# mine").unwrap();
+        symlink(dir.path().join("elsewhere"), templates.join("linked.md")).unwrap();
+
+        copy_profile(&config, DEFAULT_PROFILE, "blueteam").unwrap();
+        let copy_config = profile_config(&clients_dir(&config), "blueteam");
+        let copy_folder = copy_config.parent().unwrap();
+        let copied = Settings::load(&copy_config).unwrap();
+        assert_eq!(
+            copied.current_files,
+            Some(dir.path().join("projects/current_blueteam"))
+        );
+        assert!(dir.path().join("notes/upcoming_blueteam").is_dir());
+        assert_eq!(copied.tools, settings.tools);
+        assert_eq!(copied.key_file, copy_folder.join("key"));
+        assert_ne!(
+            read(&copied.key_file).unwrap(),
+            read(&settings.key_file).unwrap()
+        );
+        assert!(!keys::server_identity_path(&copy_config).exists());
+        assert_eq!(read_dir(copy_folder.join("projects")).unwrap().count(), 0);
+        let copied_templates = templates::templates_dir(&copy_config);
+        assert_eq!(
+            read_to_string(copied_templates.join("internal.md")).unwrap(),
+            "# mine"
+        );
+        assert_eq!(
+            read_link(copied_templates.join("linked.md")).unwrap(),
+            dir.path().join("elsewhere")
+        );
+    }
+
+    #[test]
+    fn list_leaves_other_profiles_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, _) = main_profile(dir.path());
+        let old_config = profile_config(&clients_dir(&config), "old");
+        create_dir_all(old_config.parent().unwrap()).unwrap();
+        let legacy = "server_address|10.0.0.1:443\n";
+        write(&old_config, legacy).unwrap();
+
+        let listing = list(&config).unwrap();
+        assert!(listing.contains("10.0.0.1:443"));
+        assert_eq!(read_to_string(&old_config).unwrap(), legacy);
+        assert!(!old_config.with_extension("conf.bak").exists());
+    }
+}
@@ -0,0 +1,1459 @@
+use chrono::{Local, NaiveDate};
+use colored::Colorize;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{
+        File, copy, create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, rename,
+    },
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use walkdir::WalkDir;
+
+pub const PROJECT_SCHEMA_VERSION: u32 = 1;
+
+// engagement details that can be set with project_info.
+pub const PROJECT_INFO_KEYS: [&str; 8] = [
+    "client",
+    "engagement_type",
+    "start_date",
+    "end_date",
+    "testers",
+    "time_zone",
+    "point_of_contact",
+    "tags",
+];
+
+// where a project is in its life. upcoming projects get promoted to current,
+// current ones are marked completed once testing wraps up and archiving packs
+// the whole thing away.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Stage {
+    #[default]
+    Upcoming,
+    Current,
+    Completed,
+    Archived,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [
+        Stage::Upcoming,
+        Stage::Current,
+        Stage::Completed,
+        Stage::Archived,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Upcoming => "upcoming",
+            Stage::Current => "current",
+            Stage::Completed => "completed",
+            Stage::Archived => "archived",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Stage, String> {
+        let value = value.trim().to_lowercase();
+        for stage in Stage::ALL {
+            if stage.as_str() == value {
+                return Ok(stage);
+            }
+        }
+        Err(format!(
+            "{} isn't a stage, use upcoming, current, completed or archived",
+            value
+        ))
+    }
+}
+
+// one thing remove_project deletes, or a thing it's going to leave alone and
+// why.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Removal {
+    Directory(PathBuf),
+    Config(PathBuf),
+    Distrobox(String),
+    Kept(String, String),
+}
+
+impl Removal {
+    // a what|target row for the confirmation table.
+    pub fn describe(&self) -> String {
+        match self {
+            Removal::Directory(dir) => format!("delete directory|{}", dir.display()),
+            Removal::Config(config) => format!("delete config|{}", config.display()),
+            Removal::Distrobox(boxname) => format!("remove distrobox|{}", boxname),
+            Removal::Kept(target, why) => format!("keep|{} ({})", target, why),
+        }
+    }
+}
+
+// what goes in the archive next to the files and notes, sha256sum style.
+pub const ARCHIVE_MANIFEST: &str = "MANIFEST.sha256";
+
+#[derive(Default, Clone)]
+pub struct Project {
+    pub name: String,
+    pub files: PathBuf,
+    pub notes: PathBuf,
+    pub stage: Stage,
+    pub active: bool,
+    pub boxname: String,
+    pub config: PathBuf,
+    pub archive: Option<PathBuf>,
+    pub client: String,
+    pub engagement_type: String,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub testers: Vec<String>,
+    pub time_zone: String,
+    pub point_of_contact: String,
+    pub tags: Vec<String>,
+}
+
+// what a project config file holds on disk. the version gets bumped
+// whenever the layout changes so older files can be migrated on load. the
+// file's own path isn't stored, it's wherever it was loaded from.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ProjectFile {
+    schema_version: u32,
+    name: String,
+    stage: String,
+    active: bool,
+    files: PathBuf,
+    notes: PathBuf,
+    boxname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archive: Option<PathBuf>,
+    client: String,
+    engagement_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_date: Option<String>,
+    testers: Vec<String>,
+    time_zone: String,
+    point_of_contact: String,
+    tags: Vec<String>,
+}
+
+// chrono catches the likes of 2024-02-31, the length check keeps the dates
+// zero padded since set_info compares them as strings.
+fn check_date(date: &str) -> Result<(), String> {
+    if date.len() != 10 || NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+        return Err(format!("{} isn't a date, use YYYY-MM-DD", date));
+    }
+    Ok(())
+}
+
+// only one project is active at a time, activate makes sure of that.
+pub fn active_project(projects: &[Project]) -> Option<&Project> {
+    projects.iter().find(|project| project.active)
+}
+
+// just the active project's name, for the prompt. unreadable and legacy files
+// are skipped without a word, the prompt is no place for error messages.
+pub fn active_project_name(projects_dir: &PathBuf) -> Option<String> {
+    let dir_res = read_dir(projects_dir);
+    if dir_res.is_err() {
+        return None;
+    }
+    for entry in dir_res.unwrap().filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "conf") {
+            continue;
+        }
+        let contents = read_to_string(&path).unwrap_or_default();
+        if is_pipe_delimited(&contents) {
+            continue;
+        }
+        if let Ok(file) = toml::from_str::<ProjectFile>(&contents)
+            && file.active
+        {
+            return Some(file.name);
+        }
+    }
+    None
+}
+
+// renames the verified archive into place. it's checked by now, so a failed
+// rename leaves it where it is and says so rather than cleaning it up.
+fn place_archive(temp_path: &PathBuf, archive_path: &PathBuf) -> Result<(), String> {
+    rename(temp_path, archive_path).map_err(|e| {
+        format!(
+            "the archive checked out but couldn't be moved to {}, it's been left at {}!\n{}",
+            archive_path.display(),
+            temp_path.display(),
+            e
+        )
+    })
+}
+
+// the configured roots can't go away with a project, whether the project's
+// folder is one of them or sits above one.
+fn holds_root(dir: &Path, protected: &[PathBuf]) -> bool {
+    protected.iter().any(|root| root.starts_with(dir))
+}
+
+fn distrobox_exists(boxname: &str) -> Result<bool, String> {
+    let list_res = Command::new("distrobox").arg("list").arg("--root").output();
+    if list_res.is_err() {
+        return Err(format!(
+            "error listing distroboxes!\n{}",
+            list_res.err().unwrap()
+        ));
+    }
+    let listing = String::from_utf8_lossy(&list_res.unwrap().stdout).to_string();
+    Ok(listing.lines().skip(1).any(|line| {
+        line.split('|')
+            .nth(1)
+            .is_some_and(|name| name.trim() == boxname)
+    }))
+}
+
+fn stop_distrobox(boxname: &str) -> Result<(), String> {
+    let stop_res = Command::new("distrobox")
+        .arg("stop")
+        .arg("--root")
+        .arg(boxname)
+        .arg("--yes")
+        .status();
+    if stop_res.is_err() {
+        return Err(format!(
+            "error stopping distrobox {}!\n{}",
+            boxname,
+            stop_res.err().unwrap()
+        ));
+    }
+    Ok(())
+}
+
+// a box that's already gone is fine, one that won't go away isn't.
+fn remove_distrobox(boxname: &str) -> Result<(), String> {
+    if !distrobox_exists(boxname)? {
+        return Ok(());
+    }
+    stop_distrobox(boxname)?;
+    let rm_res = Command::new("distrobox")
+        .arg("rm")
+        .arg("--root")
+        .arg("--force")
+        .arg(boxname)
+        .status();
+    if rm_res.is_err() {
+        return Err(format!(
+            "error removing distrobox {}!\n{}",
+            boxname,
+            rm_res.err().unwrap()
+        ));
+    }
+    if !rm_res.unwrap().success() {
+        return Err(format!("distrobox rm {} failed", boxname));
+    }
+    Ok(())
+}
+
+// reads the archive back and makes sure every file in the manifest is in
+// there with the same hash.
+fn verify_archive(
+    path: &PathBuf,
+    top: &str,
+    manifest: &HashMap<String, String>,
+) -> Result<(), String> {
+    let open_res = File::open(path);
+    if open_res.is_err() {
+        return Err(format!(
+            "error opening {}!\n{}",
+            path.display(),
+            open_res.err().unwrap()
+        ));
+    }
+    let mut archive = tar::Archive::new(GzDecoder::new(open_res.unwrap()));
+    let mut found = HashMap::new();
+    let entries_res = archive.entries();
+    if entries_res.is_err() {
+        return Err(format!(
+            "error reading {}!\n{}",
+            path.display(),
+            entries_res.err().unwrap()
+        ));
+    }
+    for entry_res in entries_res.unwrap() {
+        let mut entry =
+            entry_res.map_err(|e| format!("error reading {}!\n{}", path.display(), e))?;
+        let entry_path = entry
+            .path()
+            .map_err(|e| format!("error reading {}!\n{}", path.display(), e))?
+            .to_path_buf();
+        let relative = entry_path.strip_prefix(top);
+        if relative.is_err() {
+            continue;
+        }
+        let name = relative.unwrap().display().to_string();
+        if !manifest.contains_key(&name) {
+            continue;
+        }
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| format!("error reading {} from the archive!\n{}", name, e))?;
+        found.insert(name, format!("{:x}", Sha256::digest(&contents)));
+    }
+    for (name, hash) in manifest {
+        if found.get(name) != Some(hash) {
+            return Err(format!(
+                "{} didn't come back out of the archive intact, nothing was removed",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// how a folder got to its new home, so it can be put back.
+enum Moved {
+    Renamed(PathBuf, PathBuf),
+    Copied(PathBuf, PathBuf),
+}
+
+fn hash_file(path: &std::path::Path) -> Result<String, String> {
+    let open_res = File::open(path);
+    if open_res.is_err() {
+        return Err(format!(
+            "error reading {}!\n{}",
+            path.display(),
+            open_res.err().unwrap()
+        ));
+    }
+    let mut hasher = Sha256::new();
+    let copy_res = std::io::copy(&mut open_res.unwrap(), &mut hasher);
+    if copy_res.is_err() {
+        return Err(format!(
+            "error reading {}!\n{}",
+            path.display(),
+            copy_res.err().unwrap()
+        ));
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// a rename when both ends are on the same filesystem, otherwise a checked
+// copy. the source is left in place either way for the caller to deal with.
+fn move_dir(from: &PathBuf, to: &PathBuf) -> Result<Moved, String> {
+    let parent = to.parent().unwrap().to_path_buf();
+    let create_res = create_dir_all(&parent);
+    if create_res.is_err() {
+        return Err(format!(
+            "error creating {}!\n{}",
+            parent.display(),
+            create_res.err().unwrap()
+        ));
+    }
+    if rename(from, to).is_ok() {
+        return Ok(Moved::Renamed(from.clone(), to.clone()));
+    }
+    let copy_res = copy_tree(from, to);
+    if copy_res.is_err() {
+        let _ = remove_dir_all(to);
+        return Err(copy_res.err().unwrap());
+    }
+    Ok(Moved::Copied(from.clone(), to.clone()))
+}
+
+// copies the whole tree, symlinks as symlinks, and reads every file back to
+// make sure it matches.
+fn copy_tree(from: &Path, to: &Path) -> Result<(), String> {
+    for entry_res in WalkDir::new(from) {
+        if entry_res.is_err() {
+            return Err(format!(
+                "error walking {}!\n{}",
+                from.display(),
+                entry_res.err().unwrap()
+            ));
+        }
+        let entry = entry_res.unwrap();
+        let target = to.join(entry.path().strip_prefix(from).unwrap());
+        if entry.file_type().is_dir() {
+            let create_res = create_dir_all(&target);
+            if create_res.is_err() {
+                return Err(format!(
+                    "error creating {}!\n{}",
+                    target.display(),
+                    create_res.err().unwrap()
+                ));
+            }
+        } else if entry.file_type().is_symlink() {
+            let link_res = std::fs::read_link(entry.path())
+                .and_then(|link| std::os::unix::fs::symlink(link, &target));
+            if link_res.is_err() {
+                return Err(format!(
+                    "error copying link {}!\n{}",
+                    entry.path().display(),
+                    link_res.err().unwrap()
+                ));
+            }
+        } else {
+            let copy_res = copy(entry.path(), &target);
+            if copy_res.is_err() {
+                return Err(format!(
+                    "error copying {}!\n{}",
+                    entry.path().display(),
+                    copy_res.err().unwrap()
+                ));
+            }
+            if hash_file(entry.path())? != hash_file(&target)? {
+                return Err(format!(
+                    "{} doesn't match the original after copying",
+                    target.display()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// undoes moves newest first, says what it managed.
+fn roll_back(moved: &[Moved]) -> String {
+    let mut report = String::from("nothing was removed, rolled back:");
+    for step in moved.iter().rev() {
+        let (undo_res, from, to) = match step {
+            Moved::Renamed(from, to) => (rename(to, from), from, to),
+            Moved::Copied(from, to) => (remove_dir_all(to), from, to),
+        };
+        if undo_res.is_err() {
+            report.push_str(&format!(
+                "\ncouldn't undo {} -> {}, sort it out by hand! {}",
+                from.display(),
+                to.display(),
+                undo_res.err().unwrap()
+            ));
+        } else {
+            report.push_str(&format!("\n{} is back where it was", from.display()));
+        }
+    }
+    report
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// the old config and project files were key|value, one per line. going by the
+// first line alone keeps values that happen to hold = or | from fooling it.
+pub fn is_pipe_delimited(contents: &str) -> bool {
+    let first = contents
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'));
+    if first.is_none() {
+        return false;
+    }
+    let split = first.unwrap().split_once('|');
+    split.is_some_and(|(key, _)| {
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+impl Project {
+    pub fn generate_default_notes(&self, config_folder: &Path) -> String {
+        let mut notes_template = config_folder.with_file_name("note_templates");
+        if self.name.contains("external") {
+            notes_template.push("external");
+        } else if self.name.contains("internal") {
+            notes_template.push("internal");
+        } else if self.name.contains("vishing") {
+            notes_template.push("vishing");
+        } else if self.name.contains("phishing") {
+            notes_template.push("phishing");
+        } else if self.name.contains("webapp") {
+            notes_template.push("webapp");
+        } else {
+            notes_template.push("external");
+        }
+        let walkdir = WalkDir::new(&notes_template);
+        for entry in walkdir.into_iter().flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.contains(".md") {
+                let mut temp_path = self.notes.clone();
+                temp_path.push(&file_name);
+                let copy_res = copy(entry.path(), &temp_path);
+                if copy_res.is_err() {
+                    return format!(
+                        "Error copying note file {} to {}",
+                        file_name,
+                        temp_path.display()
+                    );
+                }
+                copy_res.unwrap();
+            }
+        }
+        String::from("Success!")
+    }
+
+    // moves the upcoming folders under the current ones, keeping the tree as
+    // it is. nothing in the upcoming folders is removed until both moves and
+    // the config save have worked, anything failing before that puts
+    // everything back where it was.
+    pub fn promote_project(
+        &mut self,
+        files: &Path,
+        notes: &Path,
+        template: String,
+        tools: &Path,
+        home: Option<PathBuf>,
+    ) -> String {
+        if self.stage != Stage::Upcoming {
+            return format!(
+                "Error! {} is {}, only upcoming projects can be promoted",
+                self.name,
+                self.stage.as_str()
+            );
+        }
+        let new_files_path = files.join(&self.name);
+        let new_notes_path = notes.join(&self.name);
+        for path in [&new_files_path, &new_notes_path] {
+            if path.exists() {
+                return format!(
+                    "Error! {} already exists, move it out of the way first",
+                    path.display()
+                );
+            }
+        }
+        let mut moved = Vec::new();
+        for (from, to) in [
+            (self.files.clone(), new_files_path.clone()),
+            (self.notes.clone(), new_notes_path.clone()),
+        ] {
+            let move_res = move_dir(&from, &to);
+            if move_res.is_err() {
+                return format!(
+                    "Error moving {}!\n{}\n{}",
+                    from.display(),
+                    move_res.err().unwrap(),
+                    roll_back(&moved)
+                );
+            }
+            moved.push(move_res.unwrap());
+        }
+        let old_files = self.files.clone();
+        let old_notes = self.notes.clone();
+        self.stage = Stage::Current;
+        self.files = new_files_path;
+        self.notes = new_notes_path;
+        let save_res = self.save_project();
+        if save_res != "Success!" {
+            self.stage = Stage::Upcoming;
+            self.files = old_files;
+            self.notes = old_notes;
+            return format!("{}\n{}", save_res, roll_back(&moved));
+        }
+        // the copies are checked and the config points at them, so the
+        // originals can go. a leftover is only clutter at this point.
+        for step in &moved {
+            if let Moved::Copied(from, _) = step {
+                let remove_res = remove_dir_all(from);
+                if remove_res.is_err() {
+                    println!(
+                        "{}",
+                        format!(
+                            "Error removing {}, manual clean up required!\n{}",
+                            from.display(),
+                            remove_res.err().unwrap()
+                        )
+                        .red()
+                    );
+                }
+            }
+        }
+        let distrobox_res = self.create_distrobox(template, tools, home);
+        if distrobox_res != "Success!" {
+            return format!(
+                "Error creating distrobox!\n{}\n\nThe project was still promoted, but the distrobox has not been created!",
+                distrobox_res
+            );
+        }
+        String::from("Success!")
+    }
+
+    // reads a project config, converting the old key|value format to the
+    // current schema the first time it's seen. the original is kept as .bak.
+    pub fn load(path: &PathBuf) -> Result<Project, String> {
+        let read_res = read_to_string(path);
+        if read_res.is_err() {
+            return Err(format!(
+                "error reading {}!\n{}",
+                path.display(),
+                read_res.err().unwrap()
+            ));
+        }
+        let contents = read_res.unwrap();
+        if is_pipe_delimited(&contents) {
+            let project = Project::from_legacy(&contents, path);
+            let mut backup = path.clone();
+            backup.set_extension("conf.bak");
+            let backup_res = copy(path, &backup);
+            if backup_res.is_err() {
+                return Err(format!(
+                    "error backing up {}!\n{}",
+                    path.display(),
+                    backup_res.err().unwrap()
+                ));
+            }
+            let save_res = project.save_project();
+            if save_res != "Success!" {
+                return Err(save_res);
+            }
+            return Ok(project);
+        }
+        let parse_res = toml::from_str::<ProjectFile>(&contents);
+        if parse_res.is_err() {
+            return Err(format!(
+                "error parsing {}!\n{}",
+                path.display(),
+                parse_res.err().unwrap()
+            ));
+        }
+        let file = parse_res.unwrap();
+        if file.schema_version > PROJECT_SCHEMA_VERSION {
+            return Err(format!(
+                "{} uses project schema {}, this client only knows up to {}. update the client",
+                path.display(),
+                file.schema_version,
+                PROJECT_SCHEMA_VERSION
+            ));
+        }
+        let stage_res = Stage::parse(&file.stage);
+        if stage_res.is_err() {
+            return Err(format!(
+                "error loading {}!\n{}",
+                path.display(),
+                stage_res.err().unwrap()
+            ));
+        }
+        let mut project = Project {
+            name: file.name,
+            files: file.files,
+            notes: file.notes,
+            stage: stage_res.unwrap(),
+            active: file.active,
+            boxname: file.boxname,
+            config: path.clone(),
+            archive: file.archive,
+            client: file.client,
+            engagement_type: file.engagement_type,
+            start_date: file.start_date,
+            end_date: file.end_date,
+            testers: file.testers,
+            time_zone: file.time_zone,
+            point_of_contact: file.point_of_contact,
+            tags: file.tags,
+        };
+        if project.boxname.is_empty() {
+            project.boxname = String::from("none");
+        }
+        Ok(project)
+    }
+
+    fn from_legacy(contents: &str, path: &Path) -> Project {
+        let mut project = Project::default();
+        for line in contents.lines() {
+            let split = line.split_once('|');
+            if split.is_none() {
+                continue;
+            }
+            let (key, value) = split.unwrap();
+            match key {
+                "name" => project.name = value.trim().to_string(),
+                "stage" => {
+                    if value.contains("current") {
+                        project.stage = Stage::Current;
+                    }
+                }
+                "files" => project.files = PathBuf::from(value),
+                "notes" => project.notes = PathBuf::from(value),
+                "boxname" => project.boxname = String::from(value),
+                "config" => {}
+                _ => println!(
+                    "{}",
+                    format!("dropping unknown setting {} from {}", key, path.display()).red()
+                ),
+            }
+        }
+        if project.boxname.is_empty() {
+            project.boxname = String::from("none");
+        }
+        project.config = path.to_path_buf();
+        project
+    }
+
+    pub fn get_info(&self, key: &str) -> Option<String> {
+        match key {
+            "client" => Some(self.client.clone()),
+            "engagement_type" => Some(self.engagement_type.clone()),
+            "start_date" => Some(self.start_date.clone().unwrap_or_default()),
+            "end_date" => Some(self.end_date.clone().unwrap_or_default()),
+            "testers" => Some(self.testers.join(", ")),
+            "time_zone" => Some(self.time_zone.clone()),
+            "point_of_contact" => Some(self.point_of_contact.clone()),
+            "tags" => Some(self.tags.join(", ")),
+            _ => None,
+        }
+    }
+
+    // lists are comma separated, dates are YYYY-MM-DD and an empty value
+    // clears the field.
+    pub fn set_info(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let date = || -> Result<Option<String>, String> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            check_date(value)?;
+            Ok(Some(value.to_string()))
+        };
+        match key {
+            "client" => self.client = value.to_string(),
+            "engagement_type" => self.engagement_type = value.to_string(),
+            "start_date" => self.start_date = date()?,
+            "end_date" => self.end_date = date()?,
+            "testers" => self.testers = split_list(value),
+            "time_zone" => self.time_zone = value.to_string(),
+            "point_of_contact" => self.point_of_contact = value.to_string(),
+            "tags" => self.tags = split_list(value),
+            _ => return Err(format!("unknown project field {}", key)),
+        }
+        // the dates are zero padded so comparing the strings is enough.
+        if self.start_date.is_some() && self.end_date.is_some() && self.end_date < self.start_date {
+            return Err(String::from("the end date is before the start date"));
+        }
+        Ok(())
+    }
+
+    // written to a temp file and renamed over the old one so a crash can't
+    // leave a half written config.
+    pub fn save_project(&self) -> String {
+        let file = ProjectFile {
+            schema_version: PROJECT_SCHEMA_VERSION,
+            name: self.name.trim().to_string(),
+            stage: self.stage.as_str().to_string(),
+            active: self.active,
+            files: self.files.clone(),
+            notes: self.notes.clone(),
+            boxname: self.boxname.trim().to_string(),
+            archive: self.archive.clone(),
+            client: self.client.clone(),
+            engagement_type: self.engagement_type.clone(),
+            start_date: self.start_date.clone(),
+            end_date: self.end_date.clone(),
+            testers: self.testers.clone(),
+            time_zone: self.time_zone.clone(),
+            point_of_contact: self.point_of_contact.clone(),
+            tags: self.tags.clone(),
+        };
+        let toml_res = toml::to_string_pretty(&file);
+        if toml_res.is_err() {
+            return format!(
+                "Error serializing project config!\n{}",
+                toml_res.err().unwrap()
+            );
+        }
+        let mut temp_path = self.config.clone();
+        temp_path.set_extension("tmp");
+        let conf_open_create_res = File::create(&temp_path);
+        if conf_open_create_res.is_err() {
+            return format!(
+                "Error creating new config file!\n{}",
+                conf_open_create_res.err().unwrap()
+            );
+        }
+        let mut conf_file = conf_open_create_res.unwrap();
+        let write_res = conf_file.write_all(toml_res.unwrap().as_bytes());
+        if write_res.is_err() {
+            return format!("Error writing config file!\n{}", write_res.err().unwrap());
+        }
+        let rename_res = rename(&temp_path, &self.config);
+        if rename_res.is_err() {
+            return format!(
+                "Error moving new config file into place!\n{}",
+                rename_res.err().unwrap()
+            );
+        }
+        String::from("Success!")
+    }
+
+    // works out what remove_project would delete without touching anything.
+    // directories that are one of the configured roots, or hold one, are
+    // left alone. the default project's files are the whole current_files
+    // folder. only boxes that exist are listed, and only when remove_box says
+    // this project owns its box.
+    pub fn removal_plan(
+        &self,
+        remove_box: bool,
+        protected: &[PathBuf],
+    ) -> Result<Vec<Removal>, String> {
+        let mut plan = Vec::new();
+        for dir in [&self.files, &self.notes] {
+            if dir.as_os_str().is_empty() || !dir.exists() {
+                continue;
+            }
+            if holds_root(dir, protected) {
+                plan.push(Removal::Kept(
+                    dir.display().to_string(),
+                    String::from("it's a configured folder, not just this project's"),
+                ));
+                continue;
+            }
+            plan.push(Removal::Directory(dir.clone()));
+        }
+        if self.config.exists() {
+            plan.push(Removal::Config(self.config.clone()));
+        }
+        if remove_box && distrobox_exists(&self.boxname)? {
+            plan.push(Removal::Distrobox(self.boxname.clone()));
+        }
+        if self.archive.is_some() {
+            plan.push(Removal::Kept(
+                self.archive.clone().unwrap().display().to_string(),
+                String::from("archives aren't removed with the project"),
+            ));
+        }
+        Ok(plan)
+    }
+
+    // deletes exactly what removal_plan listed. the config goes last so a
+    // failure part way leaves the project around to try again.
+    pub fn remove_project(&self, plan: &Vec<Removal>) -> String {
+        for removal in plan {
+            match removal {
+                Removal::Directory(dir) => {
+                    let remove_res = remove_dir_all(dir);
+                    if remove_res.is_err() {
+                        return format!(
+                            "Error removing {}!\n{}",
+                            dir.display(),
+                            remove_res.err().unwrap()
+                        );
+                    }
+                }
+                Removal::Distrobox(boxname) => {
+                    let remove_res = remove_distrobox(boxname);
+                    if remove_res.is_err() {
+                        return format!("Error deleting distrobox!\n{}", remove_res.err().unwrap());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if plan.contains(&Removal::Config(self.config.clone())) {
+            let config_remove_res = remove_file(&self.config);
+            if config_remove_res.is_err() {
+                return format!(
+                    "Error removing config file!\n{}",
+                    config_remove_res.err().unwrap()
+                );
+            }
+        }
+        String::from("Success!")
+    }
+
+    // packs the project away. the distrobox is stopped first so nothing is
+    // still writing into the files while they're read, then the files and
+    // notes are written to <archive>/<name>_<date>.tar.gz along with a
+    // manifest of their hashes. the box and the originals are only removed
+    // once the archive reads back clean and is in place. the template box is
+    // never removed, the default project runs in it. a project whose folders
+    // are, or hold, one of the configured roots is refused outright, same as
+    // removal_plan.
+    pub fn archive_project(
+        &mut self,
+        archive_dir: &Path,
+        distrobox: bool,
+        template: &str,
+        protected: &[PathBuf],
+    ) -> Result<PathBuf, String> {
+        if self.stage == Stage::Archived {
+            return Err(format!("{} is already archived", self.name));
+        }
+        if self.stage == Stage::Upcoming {
+            return Err(format!(
+                "{} hasn't started yet, only current or completed projects can be archived",
+                self.name
+            ));
+        }
+        for dir in [&self.files, &self.notes] {
+            if holds_root(dir, protected) {
+                return Err(format!(
+                    "{} is a configured folder, not just this project's, refusing to archive it",
+                    dir.display()
+                ));
+            }
+        }
+        if !archive_dir.is_dir() {
+            return Err(format!("{} isn't a directory", archive_dir.display()));
+        }
+        let dated = format!("{}_{}", self.name, Local::now().format("%Y-%m-%d"));
+        let archive_path = archive_dir.join(format!("{}.tar.gz", dated));
+        if archive_path.exists() {
+            return Err(format!("{} already exists", archive_path.display()));
+        }
+        let remove_box = distrobox && self.boxname != "none" && self.boxname != template;
+        if remove_box && distrobox_exists(&self.boxname)? {
+            stop_distrobox(&self.boxname)?;
+        }
+        let mut temp_path = archive_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let pack_res = self.pack(&temp_path, &dated);
+        if pack_res.is_err() {
+            let _ = remove_file(&temp_path);
+            return Err(pack_res.err().unwrap());
+        }
+        let verify_res = verify_archive(&temp_path, &dated, &pack_res.unwrap());
+        if verify_res.is_err() {
+            let _ = remove_file(&temp_path);
+            return Err(verify_res.err().unwrap());
+        }
+        // the archive is the only copy from here on, a failure past this point
+        // must never take it with it.
+        place_archive(&temp_path, &archive_path)?;
+        if remove_box {
+            let remove_res = remove_distrobox(&self.boxname);
+            if remove_res.is_err() {
+                return Err(format!(
+                    "archived to {} but the distrobox couldn't be removed, so the project was left as it was. remove the box by hand and move the archive aside before trying again!\n{}",
+                    archive_path.display(),
+                    remove_res.err().unwrap()
+                ));
+            }
+        }
+        self.stage = Stage::Archived;
+        self.active = false;
+        self.archive = Some(archive_path.clone());
+        let save_res = self.save_project();
+        if save_res != "Success!" {
+            return Err(save_res);
+        }
+        for dir in [&self.files, &self.notes] {
+            let remove_res = remove_dir_all(dir);
+            if remove_res.is_err() {
+                return Err(format!(
+                    "archived to {} but couldn't remove {}, clean it up by hand!\n{}",
+                    archive_path.display(),
+                    dir.display(),
+                    remove_res.err().unwrap()
+                ));
+            }
+        }
+        Ok(archive_path)
+    }
+
+    // writes the tarball and hands back the manifest it put in it.
+    fn pack(&self, path: &PathBuf, top: &str) -> Result<HashMap<String, String>, String> {
+        let create_res = File::create(path);
+        if create_res.is_err() {
+            return Err(format!(
+                "error creating {}!\n{}",
+                path.display(),
+                create_res.err().unwrap()
+            ));
+        }
+        let encoder = GzEncoder::new(create_res.unwrap(), Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(false);
+        let mut manifest = HashMap::new();
+        // manifest paths are relative to the top folder so sha256sum -c works
+        // from inside the unpacked archive.
+        let trees = [("files", &self.files), ("notes", &self.notes)];
+        for (prefix, dir) in trees {
+            for entry_res in WalkDir::new(dir) {
+                if entry_res.is_err() {
+                    return Err(format!(
+                        "error walking {}!\n{}",
+                        dir.display(),
+                        entry_res.err().unwrap()
+                    ));
+                }
+                let entry = entry_res.unwrap();
+                let relative = entry.path().strip_prefix(dir).unwrap();
+                let relative = PathBuf::from(prefix).join(relative);
+                let name = PathBuf::from(top).join(&relative);
+                if entry.file_type().is_file() {
+                    manifest.insert(relative.display().to_string(), hash_file(entry.path())?);
+                }
+                builder
+                    .append_path_with_name(entry.path(), &name)
+                    .map_err(|e| format!("error archiving {}!\n{}", entry.path().display(), e))?;
+            }
+        }
+        builder
+            .append_path_with_name(&self.config, format!("{}/project.conf", top))
+            .map_err(|e| format!("error archiving {}!\n{}", self.config.display(), e))?;
+        let mut names: Vec<&String> = manifest.keys().collect();
+        names.sort();
+        let mut contents = String::new();
+        for name in names {
+            contents.push_str(&format!("{}  {}\n", manifest[name], name));
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(Local::now().timestamp() as u64);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("{}/{}", top, ARCHIVE_MANIFEST),
+                contents.as_bytes(),
+            )
+            .map_err(|e| format!("error writing the archive manifest!\n{}", e))?;
+        let finish_res = builder.into_inner().and_then(|encoder| encoder.finish());
+        if finish_res.is_err() {
+            return Err(format!(
+                "error finishing {}!\n{}",
+                path.display(),
+                finish_res.err().unwrap()
+            ));
+        }
+        let sync_res = finish_res.unwrap().sync_all();
+        if sync_res.is_err() {
+            return Err(format!(
+                "error flushing {}!\n{}",
+                path.display(),
+                sync_res.err().unwrap()
+            ));
+        }
+        Ok(manifest)
+    }
+
+    pub fn create_distrobox(
+        &self,
+        template: String,
+        tools: &Path,
+        home: Option<PathBuf>,
+    ) -> String {
+        println!("stopping project distrobox and template distrobox.");
+        println!("ignore any errors about the distrobox not existing.");
+        let pdb_stop_res = Command::new("distrobox")
+            .arg("stop")
+            .arg("--root")
+            .arg(&self.boxname)
+            .arg("--yes")
+            .status();
+        if pdb_stop_res.is_err() {
+            return format!(
+                "Error stopping project distrobox!\n{}",
+                pdb_stop_res.err().unwrap()
+            );
+        }
+        let tdb_stop_res = Command::new("distrobox")
+            .arg("stop")
+            .arg("--root")
+            .arg(&template)
+            .arg("--yes")
+            .status();
+        if tdb_stop_res.is_err() {
+            return format!(
+                "Error stopping template box!\n{}",
+                tdb_stop_res.err().unwrap()
+            );
+        }
+        pdb_stop_res.unwrap();
+        tdb_stop_res.unwrap();
+        let db_remove_res = Command::new("distrobox")
+            .arg("rm")
+            .arg("--root")
+            .arg(&self.boxname)
+            .arg("--yes")
+            .status();
+        if db_remove_res.is_err() {
+            return format!(
+                "Error removing distrobox!\n{}",
+                db_remove_res.err().unwrap()
+            );
+        }
+        db_remove_res.unwrap();
+        let db_create_res = Command::new("distrobox")
+            .arg("create")
+            .arg("--root")
+            .arg("--clone")
+            .arg(template)
+            .arg("--init")
+            .arg("--volume")
+            .arg(format!("{}:/pentest:rw", &self.files.display()))
+            .arg(format!("{}:/tools:rw", tools.display()))
+            .arg("--name")
+            .arg(&self.boxname)
+            .arg("--yes")
+            .status();
+        if db_create_res.is_err() {
+            return format!(
+                "Error creating distrobox!\n{}",
+                db_create_res.err().unwrap()
+            );
+        }
+        db_create_res.unwrap();
+        println!("{}", "distrobox created!".green());
+        println!("starting it up to do some setup stuff.");
+        let start_res = Command::new("distrobox")
+            .arg("enter")
+            .arg("--root")
+            .arg(&self.boxname)
+            .arg("--")
+            .arg("exit")
+            .status();
+        if start_res.is_err() {
+            return format!("Error starting distrobox!\n{}", start_res.err().unwrap());
+        }
+        String::from("Success!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+
+    #[test]
+    fn baseline_project_file_is_migrated_with_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acme.conf");
+        let legacy = format!(
+            "name|acme\nfiles|/home/pyro/projects/current/acme=2024\nnotes|/home/pyro/notes/current/acme\nboxname|acme_box\nconfig|{}\nstage|current",
+            path.display()
+        );
+        write(&path, &legacy).unwrap();
+
+        let project = Project::load(&path).unwrap();
+        assert_eq!(project.name, "acme");
+        assert_eq!(project.stage, Stage::Current);
+        assert_eq!(
+            project.files,
+            PathBuf::from("/home/pyro/projects/current/acme=2024")
+        );
+        assert_eq!(project.boxname, "acme_box");
+        assert_eq!(
+            read_to_string(dir.path().join("acme.conf.bak")).unwrap(),
+            legacy
+        );
+
+        let migrated = read_to_string(&path).unwrap();
+        assert!(!is_pipe_delimited(&migrated));
+        assert!(migrated.contains("schema_version"));
+        let reloaded = Project::load(&path).unwrap();
+        assert_eq!(reloaded.files, project.files);
+        assert_eq!(reloaded.stage, Stage::Current);
+    }
+
+    #[test]
+    fn active_project_name_skips_what_it_cant_read() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(active_project_name(&dir.path().join("missing")), None);
+        write(dir.path().join("broken.conf"), "name = [").unwrap();
+        write(dir.path().join("old.conf"), "name|old\nstage|current").unwrap();
+        assert_eq!(active_project_name(&dir.path().to_path_buf()), None);
+
+        let project = Project {
+            name: String::from("acme"),
+            active: true,
+            config: dir.path().join("acme.conf"),
+            ..Default::default()
+        };
+        assert_eq!(project.save_project(), "Success!");
+        assert_eq!(
+            active_project_name(&dir.path().to_path_buf()),
+            Some(String::from("acme"))
+        );
+        // nothing got migrated behind our back.
+        assert!(!dir.path().join("old.conf.bak").exists());
+    }
+
+    #[test]
+    fn toml_project_with_pipes_in_values_is_not_legacy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acme.conf");
+        let project = Project {
+            name: String::from("acme"),
+            files: PathBuf::from("/tmp/acme|files"),
+            notes: PathBuf::from("/tmp/acme_notes"),
+            boxname: String::from("none"),
+            config: path.clone(),
+            ..Default::default()
+        };
+        assert_eq!(project.save_project(), "Success!");
+
+        let loaded = Project::load(&path).unwrap();
+        assert_eq!(loaded.files, project.files);
+        assert!(!dir.path().join("acme.conf.bak").exists());
+    }
+
+    fn archivable_project(root: &std::path::Path) -> Project {
+        let project = Project {
+            name: String::from("acme"),
+            stage: Stage::Completed,
+            files: root.join("files/acme"),
+            notes: root.join("notes/acme"),
+            boxname: String::from("none"),
+            config: root.join("acme.conf"),
+            ..Default::default()
+        };
+        create_dir_all(project.files.join("loot")).unwrap();
+        create_dir_all(&project.notes).unwrap();
+        write(project.files.join("loot/hashes.txt"), "admin:1234").unwrap();
+        write(project.notes.join("findings.md"), "# findings").unwrap();
+        assert_eq!(project.save_project(), "Success!");
+        project
+    }
+
+    #[test]
+    fn packed_archive_verifies_and_catches_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = archivable_project(dir.path());
+        let path = dir.path().join("acme.tar.gz");
+        let manifest = project.pack(&path, "acme_top").unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert!(manifest.contains_key("files/loot/hashes.txt"));
+        assert!(manifest.contains_key("notes/findings.md"));
+        verify_archive(&path, "acme_top", &manifest).unwrap();
+
+        let mut tampered = manifest.clone();
+        tampered.insert(String::from("notes/findings.md"), String::from("00"));
+        assert!(verify_archive(&path, "acme_top", &tampered).is_err());
+        let mut missing = manifest.clone();
+        missing.insert(String::from("notes/extra.md"), String::from("00"));
+        assert!(verify_archive(&path, "acme_top", &missing).is_err());
+        assert!(verify_archive(&path, "other_top", &manifest).is_err());
+    }
+
+    #[test]
+    fn archive_project_packs_and_removes_the_originals() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = archivable_project(dir.path());
+        let archive_dir = dir.path().join("archive");
+        create_dir_all(&archive_dir).unwrap();
+        let protected = vec![dir.path().join("files"), dir.path().join("notes")];
+
+        let archive_path = project
+            .archive_project(&archive_dir, true, "template", &protected)
+            .unwrap();
+        assert!(archive_path.starts_with(&archive_dir));
+        assert!(archive_path.exists());
+        assert!(!project.files.exists());
+        assert!(!project.notes.exists());
+        assert!(dir.path().join("files").exists());
+        let reloaded = Project::load(&project.config).unwrap();
+        assert_eq!(reloaded.stage, Stage::Archived);
+        assert_eq!(reloaded.archive, Some(archive_path));
+    }
+
+    #[test]
+    fn archive_project_refuses_configured_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = archivable_project(dir.path());
+        let archive_dir = dir.path().join("archive");
+        create_dir_all(&archive_dir).unwrap();
+
+        // the files folder is a root itself, and the notes folder holds one.
+        let protected = vec![project.files.clone(), project.notes.join("upcoming")];
+        let archive_res = project.archive_project(&archive_dir, false, "template", &protected);
+        assert!(archive_res.is_err());
+        assert!(archive_res.err().unwrap().contains("configured folder"));
+        let protected = vec![project.notes.join("upcoming")];
+        assert!(
+            project
+                .archive_project(&archive_dir, false, "template", &protected)
+                .is_err()
+        );
+
+        assert!(project.files.join("loot/hashes.txt").exists());
+        assert!(project.notes.join("findings.md").exists());
+        assert_eq!(read_dir(&archive_dir).unwrap().count(), 0);
+        assert_eq!(
+            Project::load(&project.config).unwrap().stage,
+            Stage::Completed
+        );
+    }
+
+    #[test]
+    fn removal_plan_keeps_configured_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = archivable_project(dir.path());
+        project.archive = Some(dir.path().join("archive/acme.tar.gz"));
+
+        let plan = project.removal_plan(false, &Vec::new()).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                Removal::Directory(project.files.clone()),
+                Removal::Directory(project.notes.clone()),
+                Removal::Config(project.config.clone()),
+                Removal::Kept(
+                    dir.path().join("archive/acme.tar.gz").display().to_string(),
+                    String::from("archives aren't removed with the project"),
+                ),
+            ]
+        );
+
+        // the default project's notes are the whole notes root.
+        project.notes = dir.path().join("notes");
+        project.archive = None;
+        let protected = vec![dir.path().join("notes"), dir.path().join("files")];
+        let plan = project.removal_plan(false, &protected).unwrap();
+        assert_eq!(plan.len(), 3);
+        assert_eq!(plan[0], Removal::Directory(project.files.clone()));
+        assert!(matches!(plan[1], Removal::Kept(..)));
+        assert_eq!(plan[2], Removal::Config(project.config.clone()));
+    }
+
+    #[test]
+    fn remove_project_only_removes_the_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = archivable_project(dir.path());
+        // a folder that's gone already isn't in the plan at all.
+        remove_dir_all(&project.notes).unwrap();
+        project.files = dir.path().join("files");
+        let protected = vec![dir.path().join("files/upcoming")];
+        let plan = project.removal_plan(false, &protected).unwrap();
+        assert_eq!(plan.len(), 2);
+        assert!(matches!(plan[0], Removal::Kept(..)));
+
+        assert_eq!(project.remove_project(&plan), "Success!");
+        assert!(dir.path().join("files/acme/loot/hashes.txt").exists());
+        assert!(!project.config.exists());
+    }
+
+    fn upcoming_project(root: &std::path::Path) -> Project {
+        let project = Project {
+            name: String::from("acme"),
+            files: root.join("upcoming/files/acme"),
+            notes: root.join("upcoming/notes/acme"),
+            boxname: String::from("none"),
+            config: root.join("acme.conf"),
+            ..Default::default()
+        };
+        create_dir_all(project.files.join("web/scans")).unwrap();
+        create_dir_all(project.files.join("internal/scans")).unwrap();
+        create_dir_all(&project.notes).unwrap();
+        write(project.files.join("web/scans/nmap.txt"), "web").unwrap();
+        write(project.files.join("internal/scans/nmap.txt"), "internal").unwrap();
+        write(project.notes.join("scope.md"), "# scope").unwrap();
+        project
+    }
+
+    #[test]
+    fn copied_tree_keeps_same_named_files_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = upcoming_project(dir.path());
+        let copy = dir.path().join("copy");
+        copy_tree(&project.files, &copy).unwrap();
+        assert_eq!(
+            read_to_string(copy.join("web/scans/nmap.txt")).unwrap(),
+            "web"
+        );
+        assert_eq!(
+            read_to_string(copy.join("internal/scans/nmap.txt")).unwrap(),
+            "internal"
+        );
+
+        let moved = dir.path().join("current/acme");
+        let move_res = move_dir(&copy, &moved).unwrap();
+        assert!(matches!(move_res, Moved::Renamed(..)));
+        assert!(!copy.exists());
+        assert_eq!(
+            read_to_string(moved.join("web/scans/nmap.txt")).unwrap(),
+            "web"
+        );
+        assert_eq!(
+            read_to_string(moved.join("internal/scans/nmap.txt")).unwrap(),
+            "internal"
+        );
+    }
+
+    #[test]
+    fn failed_second_move_rolls_back_the_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = upcoming_project(dir.path());
+        assert_eq!(project.save_project(), "Success!");
+        remove_dir_all(&project.notes).unwrap();
+        let files = dir.path().join("current/files");
+        let notes = dir.path().join("current/notes");
+
+        let promote_res =
+            project.promote_project(&files, &notes, String::new(), &PathBuf::new(), None);
+        assert!(promote_res.contains("rolled back"));
+        assert!(project.files.join("web/scans/nmap.txt").exists());
+        assert!(!files.join("acme").exists());
+        assert_eq!(project.stage, Stage::Upcoming);
+        assert_eq!(
+            Project::load(&project.config).unwrap().stage,
+            Stage::Upcoming
+        );
+    }
+
+    #[test]
+    fn failed_save_puts_the_folders_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = upcoming_project(dir.path());
+        project.config = dir.path().join("missing/acme.conf");
+        let upcoming_files = project.files.clone();
+        let upcoming_notes = project.notes.clone();
+        let files = dir.path().join("current/files");
+        let notes = dir.path().join("current/notes");
+
+        let promote_res =
+            project.promote_project(&files, &notes, String::new(), &PathBuf::new(), None);
+        assert!(promote_res.contains("rolled back"));
+        assert_eq!(project.stage, Stage::Upcoming);
+        assert_eq!(project.files, upcoming_files);
+        assert_eq!(project.notes, upcoming_notes);
+        assert_eq!(
+            read_to_string(upcoming_files.join("internal/scans/nmap.txt")).unwrap(),
+            "internal"
+        );
+        assert!(upcoming_notes.join("scope.md").exists());
+        assert!(!files.join("acme").exists());
+        assert!(!notes.join("acme").exists());
+    }
+
+    #[test]
+    fn failed_rename_keeps_the_verified_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = archivable_project(dir.path());
+        let temp_path = dir.path().join("acme.tar.gz.tmp");
+        let manifest = project.pack(&temp_path, "acme").unwrap();
+        verify_archive(&temp_path, "acme", &manifest).unwrap();
+        // a folder in the way fails the rename even for root.
+        let archive_path = dir.path().join("acme.tar.gz");
+        create_dir_all(archive_path.join("in_the_way")).unwrap();
+
+        let place_res = place_archive(&temp_path, &archive_path);
+        assert!(place_res.err().unwrap().contains("it's been left at"));
+        verify_archive(&temp_path, "acme", &manifest).unwrap();
+    }
+
+    #[test]
+    fn dates_have_to_exist() {
+        assert!(check_date("2024-02-29").is_ok());
+        assert!(check_date("2024-12-31").is_ok());
+        assert!(check_date("2024-02-31").is_err());
+        assert!(check_date("2023-02-29").is_err());
+        assert!(check_date("2024-13-01").is_err());
+        assert!(check_date("2024-2-05").is_err());
+        assert!(check_date("02/05/2024").is_err());
+
+        let mut project = Project::default();
+        assert!(project.set_info("start_date", "2024-02-31").is_err());
+        assert_eq!(project.start_date, None);
+        project.set_info("start_date", "2024-02-28").unwrap();
+        assert!(project.set_info("end_date", "2024-02-27").is_err());
+    }
+}
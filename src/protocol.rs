@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+
+pub struct Server {
+    pub address: String,
+    pub id: usize,
+}
+
+#[derive(Clone)]
+pub struct Message {
+    pub source: Destination,
+    pub destination: Destination,
+    pub content: String,
+    pub payload: Option<ServerMessage>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Destination {
+    Console,
+    Server,
+    Control,
+}
+
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+pub const CAPABILITIES: [&str; 3] = ["commands", "events", "file_chunks"];
+
+// we talk at the lower of the two versions, as long as both sides still
+// speak it. a peer that has moved on past everything we know is turned away
+// here rather than failing on some message later.
+pub fn agree_version(version: u16, min_version: u16) -> Result<u16, String> {
+    let agreed = version.min(PROTOCOL_VERSION);
+    if agreed < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "peer speaks protocol v{}, we need at least v{}",
+            version, MIN_PROTOCOL_VERSION
+        ));
+    }
+    if agreed < min_version {
+        return Err(format!(
+            "peer needs at least protocol v{}, we only speak up to v{}",
+            min_version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(agreed)
+}
+
+// everything that crosses the wire between the client and the server. frames
+// are json objects tagged with "type" so new variants don't break old peers
+// that just skip what they don't understand.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    // version is the newest the sender speaks, min_version the oldest it
+    // still accepts. peers from before min_version existed send neither.
+    Hello {
+        version: u16,
+        #[serde(default)]
+        min_version: u16,
+        capabilities: Vec<String>,
+    },
+    Auth {
+        name: String,
+        challenge: Vec<u8>,
+        public_key: Vec<u8>,
+    },
+    AuthChallenge {
+        challenge: Vec<u8>,
+        public_key: Vec<u8>,
+        identity_key: Vec<u8>,
+        identity_proof: Vec<u8>,
+        proof: Vec<u8>,
+    },
+    AuthProof {
+        proof: Vec<u8>,
+    },
+    AuthResult {
+        accepted: bool,
+        message: String,
+    },
+    Enroll {
+        name: String,
+        public_key: Vec<u8>,
+    },
+    EnrollAccept {
+        public_key: Vec<u8>,
+        identity_key: Vec<u8>,
+        identity_proof: Vec<u8>,
+    },
+    EnrollKey {
+        key: Vec<u8>,
+    },
+    Command {
+        id: u64,
+        command: String,
+    },
+    Response {
+        id: u64,
+        content: String,
+    },
+    Event {
+        kind: String,
+        content: String,
+    },
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+    FileChunk {
+        id: u64,
+        name: String,
+        offset: u64,
+        data: Vec<u8>,
+        last: bool,
+    },
+    Heartbeat {
+        timestamp: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind {
+    CheckIn,
+    TaskResult,
+    Chat,
+    Error,
+    Other,
+}
+
+impl ServerMessage {
+    pub fn hello() -> ServerMessage {
+        ServerMessage::Hello {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|cap| cap.to_string()).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("error serializing message!\n{}", e))
+    }
+
+    pub fn from_json(json: &[u8]) -> Result<ServerMessage, String> {
+        let parse_res = serde_json::from_slice(json);
+        if parse_res.is_err() {
+            return Err(format!(
+                "error parsing message from server!\n{}",
+                parse_res.err().unwrap()
+            ));
+        }
+        Ok(parse_res.unwrap())
+    }
+
+    // sorts whatever the server pushed at us into the handful of things the
+    // console knows how to show.
+    pub fn event_kind(&self) -> EventKind {
+        match self {
+            ServerMessage::Event { kind, .. } => match kind.as_str() {
+                "checkin" | "check_in" | "agent_checkin" => EventKind::CheckIn,
+                "task_result" | "result" => EventKind::TaskResult,
+                "chat" | "operator_chat" => EventKind::Chat,
+                "error" => EventKind::Error,
+                _ => EventKind::Other,
+            },
+            ServerMessage::Error { .. } => EventKind::Error,
+            _ => EventKind::Other,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ServerMessage::Hello {
+                version,
+                capabilities,
+                ..
+            } => format!(
+                "server speaks protocol v{} ({})",
+                version,
+                capabilities.join(", ")
+            ),
+            ServerMessage::Auth { name, .. } => format!("{} wants to authenticate", name),
+            ServerMessage::AuthChallenge { .. } => String::from("authentication challenge"),
+            ServerMessage::AuthProof { .. } => String::from("authentication proof"),
+            ServerMessage::AuthResult { accepted, message } => {
+                format!("authentication accepted: {} {}", accepted, message)
+            }
+            ServerMessage::Enroll { name, .. } => format!("{} wants to enroll", name),
+            ServerMessage::EnrollAccept { .. } => String::from("enrollment accepted"),
+            ServerMessage::EnrollKey { .. } => String::from("enrollment key"),
+            ServerMessage::Command { id, command } => format!("command {}: {}", id, command),
+            ServerMessage::Response { content, .. } => content.clone(),
+            ServerMessage::Event { kind, content } => format!("[{}] {}", kind, content),
+            ServerMessage::Error { message, .. } => format!("error from server! {}", message),
+            ServerMessage::FileChunk {
+                name, offset, data, ..
+            } => format!("{} bytes of {} at offset {}", data.len(), name, offset),
+            ServerMessage::Heartbeat { timestamp } => format!("heartbeat at {}", timestamp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_variant_round_trips() {
+        let messages = vec![
+            ServerMessage::hello(),
+            ServerMessage::Auth {
+                name: String::from("main_attacker"),
+                challenge: vec![1; 32],
+                public_key: vec![2; 32],
+            },
+            ServerMessage::AuthChallenge {
+                challenge: vec![3; 32],
+                public_key: vec![4; 32],
+                identity_key: vec![5; 32],
+                identity_proof: vec![6; 48],
+                proof: vec![7; 48],
+            },
+            ServerMessage::AuthProof { proof: vec![8; 48] },
+            ServerMessage::AuthResult {
+                accepted: true,
+                message: String::from("welcome back"),
+            },
+            ServerMessage::Enroll {
+                name: String::from("main_attacker"),
+                public_key: vec![9; 32],
+            },
+            ServerMessage::EnrollAccept {
+                public_key: vec![10; 32],
+                identity_key: vec![11; 32],
+                identity_proof: vec![12; 48],
+            },
+            ServerMessage::EnrollKey { key: vec![13; 60] },
+            ServerMessage::Command {
+                id: 7,
+                command: String::from("echo a|||b"),
+            },
+            ServerMessage::Response {
+                id: 7,
+                content: String::from("a|||b\n"),
+            },
+            ServerMessage::Event {
+                kind: String::from("checkin"),
+                content: String::from("new agent"),
+            },
+            ServerMessage::Error {
+                id: None,
+                message: String::from("nope"),
+            },
+            ServerMessage::Error {
+                id: Some(8),
+                message: String::from("unknown command"),
+            },
+            ServerMessage::FileChunk {
+                id: 9,
+                name: String::from("loot.zip"),
+                offset: 4096,
+                data: vec![0, 255, 128],
+                last: true,
+            },
+            ServerMessage::Heartbeat {
+                timestamp: u64::MAX,
+            },
+        ];
+        for message in messages {
+            let json = message.to_json().unwrap();
+            assert_eq!(ServerMessage::from_json(&json), Ok(message));
+        }
+    }
+
+    #[test]
+    fn junk_is_rejected_cleanly() {
+        let unknown = ServerMessage::from_json(br#"{"type":"teleport","id":1}"#);
+        assert!(unknown.err().unwrap().starts_with("error parsing message"));
+        assert!(ServerMessage::from_json(br#"{"id":1,"command":"ls"}"#).is_err());
+        assert!(ServerMessage::from_json(br#"{"type":"command","id":1}"#).is_err());
+        assert!(ServerMessage::from_json(b"not json").is_err());
+        // too big for the field rather than wrapped around.
+        let huge =
+            ServerMessage::from_json(br#"{"type":"hello","version":70000,"capabilities":[]}"#);
+        assert!(huge.is_err());
+    }
+
+    #[test]
+    fn versions_are_agreed_or_refused() {
+        assert_eq!(agree_version(PROTOCOL_VERSION, 0), Ok(PROTOCOL_VERSION));
+        // a newer peer that still speaks ours comes down to it.
+        assert_eq!(
+            agree_version(PROTOCOL_VERSION + 5, MIN_PROTOCOL_VERSION),
+            Ok(PROTOCOL_VERSION)
+        );
+        // one that has dropped ours is refused.
+        let newer = agree_version(PROTOCOL_VERSION + 5, PROTOCOL_VERSION + 1);
+        assert!(newer.err().unwrap().contains("needs at least"));
+        assert!(agree_version(MIN_PROTOCOL_VERSION - 1, 0).is_err());
+
+        // peers from before min_version still parse.
+        let old = ServerMessage::from_json(br#"{"type":"hello","version":1,"capabilities":[]}"#);
+        assert_eq!(
+            old,
+            Ok(ServerMessage::Hello {
+                version: 1,
+                min_version: 0,
+                capabilities: Vec::new(),
+            })
+        );
+    }
+}
@@ -0,0 +1,588 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, read_to_string, rename};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::print_error;
+use crate::project::is_pipe_delimited;
+
+pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:31337";
+
+// every setting the config file knows about, in the order they get shown.
+pub const KEYS: [&str; 11] = [
+    "server_address",
+    "key_file",
+    "distrobox",
+    "templatebox",
+    "current_files",
+    "current_notes",
+    "upcoming_files",
+    "upcoming_notes",
+    "archive",
+    "tools",
+    "terminal",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server_address: String,
+    pub key_file: PathBuf,
+    pub distrobox: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templatebox: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_files: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_notes: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upcoming_files: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upcoming_notes: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terminal: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            server_address: String::from(DEFAULT_SERVER_ADDRESS),
+            key_file: PathBuf::new(),
+            distrobox: true,
+            templatebox: None,
+            current_files: None,
+            current_notes: None,
+            upcoming_files: None,
+            upcoming_notes: None,
+            archive: None,
+            tools: None,
+            terminal: None,
+        }
+    }
+}
+
+fn optional(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.to_string())
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "yes" | "y" | "true" | "on" => Ok(true),
+        "no" | "n" | "false" | "off" => Ok(false),
+        _ => Err(format!("{} isn't yes or no", value.trim())),
+    }
+}
+
+// the old key|value format, one setting per line.
+pub fn is_legacy(contents: &str) -> bool {
+    is_pipe_delimited(contents)
+}
+
+impl Settings {
+    // defaults that depend on where the config lives.
+    pub fn for_config(config: &Path) -> Self {
+        Settings {
+            key_file: default_key_file(config),
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let path = |path: &Option<PathBuf>| path.as_ref().map(|p| p.display().to_string());
+        match key {
+            "server_address" => Some(self.server_address.clone()),
+            "key_file" => Some(self.key_file.display().to_string()),
+            "distrobox" => {
+                if self.distrobox {
+                    return Some(String::from("yes"));
+                }
+                Some(String::from("no"))
+            }
+            "templatebox" => self.templatebox.clone(),
+            "current_files" => path(&self.current_files),
+            "current_notes" => path(&self.current_notes),
+            "upcoming_files" => path(&self.upcoming_files),
+            "upcoming_notes" => path(&self.upcoming_notes),
+            "archive" => path(&self.archive),
+            "tools" => path(&self.tools),
+            "terminal" => self.terminal.clone(),
+            _ => None,
+        }
+    }
+
+    // sets a single setting from its text form. an empty value clears the
+    // optional settings.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "server_address" => {
+                if optional(value).is_none() {
+                    return Err(String::from("server_address can't be empty"));
+                }
+                self.server_address = value.trim().to_string();
+            }
+            "key_file" => {
+                if optional(value).is_none() {
+                    return Err(String::from("key_file can't be empty"));
+                }
+                self.key_file = PathBuf::from(value.trim());
+            }
+            "distrobox" => self.distrobox = parse_bool(value)?,
+            "templatebox" => self.templatebox = optional(value),
+            "current_files" => self.current_files = optional(value).map(PathBuf::from),
+            "current_notes" => self.current_notes = optional(value).map(PathBuf::from),
+            "upcoming_files" => self.upcoming_files = optional(value).map(PathBuf::from),
+            "upcoming_notes" => self.upcoming_notes = optional(value).map(PathBuf::from),
+            "archive" => self.archive = optional(value).map(PathBuf::from),
+            "tools" => self.tools = optional(value).map(PathBuf::from),
+            "terminal" => self.terminal = optional(value),
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    // stricter than set, for changes made by hand from the cli. paths have
+    // to already exist so a typo doesn't quietly break the next command.
+    pub fn check(key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "key_file" | "current_files" | "current_notes" | "upcoming_files"
+            | "upcoming_notes" | "archive" | "tools"
+                if optional(value).is_some() && !PathBuf::from(value.trim()).exists() =>
+            {
+                return Err(format!("{} doesn't exist", value.trim()));
+            }
+            "distrobox" => {
+                let lower = value.trim().to_lowercase();
+                if lower != "yes" && lower != "no" {
+                    return Err(String::from("distrobox has to be yes or no"));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let port = self.server_address.rsplit_once(':');
+        if port.is_none() || port.unwrap().1.parse::<u16>().is_err() {
+            return Err(format!(
+                "server_address {} needs to be host:port",
+                self.server_address
+            ));
+        }
+        if self.key_file.as_os_str().is_empty() {
+            return Err(String::from("key_file isn't set"));
+        }
+        Ok(())
+    }
+
+    pub fn from_legacy(contents: &str, config: &Path) -> Result<Self, String> {
+        let mut settings = Settings::for_config(config);
+        for line in contents.lines() {
+            if !line.contains('|') {
+                continue;
+            }
+            let (key, value) = line.split_once('|').unwrap();
+            if !KEYS.contains(&key.trim()) {
+                print_error(
+                    "unknown setting in old config file, dropping it",
+                    Some(line.to_string()),
+                );
+                continue;
+            }
+            let set_res = settings.set(key.trim(), value);
+            if set_res.is_err() {
+                return Err(format!(
+                    "error migrating line {}\n{}",
+                    line,
+                    set_res.err().unwrap()
+                ));
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn from_toml(contents: &str, config: &Path) -> Result<Self, String> {
+        let parse_res = toml::from_str::<Settings>(contents);
+        if parse_res.is_err() {
+            return Err(format!(
+                "error parsing config file!\n{}",
+                parse_res.err().unwrap()
+            ));
+        }
+        let mut settings = parse_res.unwrap();
+        if settings.key_file.as_os_str().is_empty() {
+            settings.key_file = default_key_file(config);
+        }
+        Ok(settings)
+    }
+
+    // reads the config, converting an old pipe delimited one to toml the
+    // first time it's seen. the original is kept next to it as .bak. nothing
+    // is validated yet, the caller may still have overrides to lay on top.
+    pub fn read(config: &Path) -> Result<Self, String> {
+        let read_res = read_to_string(config);
+        if read_res.is_err() {
+            return Err(format!(
+                "error reading config file!\n{}",
+                read_res.err().unwrap()
+            ));
+        }
+        let contents = read_res.unwrap();
+        let settings;
+        if is_legacy(&contents) {
+            settings = Settings::from_legacy(&contents, config)?;
+            let backup = config.with_extension("conf.bak");
+            let backup_res = std::fs::copy(config, &backup);
+            if backup_res.is_err() {
+                return Err(format!(
+                    "error backing up old config file!\n{}",
+                    backup_res.err().unwrap()
+                ));
+            }
+            settings.save(config)?;
+            println!(
+                "migrated config file to the new format, the old one is at {}",
+                backup.display()
+            );
+        } else {
+            settings = Settings::from_toml(&contents, config)?;
+        }
+        Ok(settings)
+    }
+
+    // what another profile's config says, without migrating it behind that
+    // profile's back. an old pipe delimited one is parsed and left as it is.
+    pub fn peek(config: &Path) -> Result<Self, String> {
+        let contents =
+            read_to_string(config).map_err(|e| format!("error reading config file!\n{}", e))?;
+        if is_legacy(&contents) {
+            return Settings::from_legacy(&contents, config);
+        }
+        Settings::from_toml(&contents, config)
+    }
+
+    pub fn load(config: &Path) -> Result<Self, String> {
+        let settings = Settings::read(config)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // the config file with the environment and command line laid over it.
+    // a bad value in the file is fine as long as something overrides it.
+    pub fn load_with(config: &Path, overrides: &Overrides) -> Result<Self, String> {
+        let mut settings = Settings::read(config)?;
+        overrides.apply(&mut settings)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // writes to a temp file and renames it over the config so a crash never
+    // leaves half a config behind.
+    pub fn save(&self, config: &Path) -> Result<(), String> {
+        let toml_res = toml::to_string_pretty(self);
+        if toml_res.is_err() {
+            return Err(format!(
+                "error serializing settings!\n{}",
+                toml_res.err().unwrap()
+            ));
+        }
+        let temp_path = config.with_extension("tmp");
+        let file_res = File::create(&temp_path);
+        if file_res.is_err() {
+            return Err(format!(
+                "error creating config file!\n{}",
+                file_res.err().unwrap()
+            ));
+        }
+        let mut file = file_res.unwrap();
+        let write_res = file.write_all(toml_res.unwrap().as_bytes());
+        if write_res.is_err() {
+            return Err(format!(
+                "error writing config file!\n{}",
+                write_res.err().unwrap()
+            ));
+        }
+        let _ = file.sync_all();
+        let rename_res = rename(&temp_path, config);
+        if rename_res.is_err() {
+            return Err(format!(
+                "error moving new config file into place!\n{}",
+                rename_res.err().unwrap()
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn default_key_file(config: &Path) -> PathBuf {
+    config.with_file_name("key")
+}
+
+// names the settings the config file itself sets, so the rest can be shown
+// as defaults.
+pub fn file_keys(config: &Path) -> Vec<String> {
+    let read_res = read_to_string(config);
+    if read_res.is_err() {
+        return Vec::new();
+    }
+    let parse_res = read_res.unwrap().parse::<toml::Table>();
+    if parse_res.is_err() {
+        return Vec::new();
+    }
+    parse_res.unwrap().keys().cloned().collect()
+}
+
+pub fn env_var(key: &str) -> String {
+    format!("TETANUS_{}", key.to_uppercase())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Flag,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::File => "config file",
+            Source::Env => "environment",
+            Source::Flag => "command line",
+        }
+    }
+}
+
+// values laid over the config file. later layers win:
+// defaults < config file < TETANUS_* environment < --set < --server
+// each entry remembers where it was given so bad values can be traced back.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    entries: Vec<(String, String, Source, String)>,
+}
+
+impl Overrides {
+    pub fn new(sets: &Vec<String>, server: Option<String>) -> Result<Self, String> {
+        let mut overrides = Overrides::default();
+        for key in KEYS {
+            if let Ok(value) = std::env::var(env_var(key)) {
+                overrides
+                    .entries
+                    .push((key.to_string(), value, Source::Env, env_var(key)));
+            }
+        }
+        for set in sets {
+            let split = set.split_once('=');
+            if split.is_none() {
+                return Err(format!("--set {} needs to look like key=value", set));
+            }
+            let (key, value) = split.unwrap();
+            if !KEYS.contains(&key.trim()) {
+                return Err(format!("--set {}, unknown setting {}", set, key.trim()));
+            }
+            overrides.entries.push((
+                key.trim().to_string(),
+                value.to_string(),
+                Source::Flag,
+                format!("--set {}", key.trim()),
+            ));
+        }
+        if let Some(server) = server {
+            overrides.entries.push((
+                String::from("server_address"),
+                server,
+                Source::Flag,
+                String::from("--server"),
+            ));
+        }
+        Ok(overrides)
+    }
+
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        for (key, value, _, origin) in &self.entries {
+            let set_res = settings.set(key, value);
+            if set_res.is_err() {
+                return Err(format!(
+                    "bad value from {}\n{}",
+                    origin,
+                    set_res.err().unwrap()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // where the value in effect for a setting came from.
+    pub fn source(&self, key: &str, file_keys: &[String]) -> Source {
+        let last = self
+            .entries
+            .iter()
+            .rev()
+            .find(|(name, _, _, _)| name == key);
+        if let Some((_, _, source, _)) = last {
+            return *source;
+        }
+        if file_keys.iter().any(|name| name == key) {
+            return Source::File;
+        }
+        Source::Default
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::config_command;
+    use std::fs::write;
+
+    #[test]
+    fn legacy_is_decided_by_the_first_line() {
+        assert!(is_legacy(
+            "server_address|127.0.0.1:31337\ncurrent_files|/home/pyro/a=b\n"
+        ));
+        assert!(is_legacy("\n# old\nterminal|konsole -e\n"));
+        assert!(!is_legacy(
+            "server_address = \"127.0.0.1:31337\"\nterminal = \"sh -c a|b\"\n"
+        ));
+        assert!(!is_legacy("[section]\nkey|value\n"));
+        assert!(!is_legacy("|value\n"));
+        assert!(!is_legacy(""));
+    }
+
+    #[test]
+    fn overrides_are_applied_before_validating() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config.conf");
+        write(&config, "server_address = \"no port here\"\n").unwrap();
+        assert!(Settings::load(&config).is_err());
+
+        let overrides = Overrides::new(&Vec::new(), Some(String::from("10.0.0.1:443"))).unwrap();
+        let settings = Settings::load_with(&config, &overrides).unwrap();
+        assert_eq!(settings.server_address, "10.0.0.1:443");
+
+        // and a bad override is still caught.
+        write(&config, "server_address = \"127.0.0.1:31337\"\n").unwrap();
+        let overrides =
+            Overrides::new(&vec![String::from("server_address=no port")], None).unwrap();
+        assert!(Settings::load_with(&config, &overrides).is_err());
+    }
+
+    #[test]
+    fn bad_override_names_where_it_came_from() {
+        let mut settings = Settings::default();
+        let overrides = Overrides::new(&Vec::new(), Some(String::from(" "))).unwrap();
+        let error = overrides.apply(&mut settings).err().unwrap();
+        assert!(error.starts_with("bad value from --server\n"));
+
+        let overrides = Overrides::new(&vec![String::from("distrobox=maybe")], None).unwrap();
+        let error = overrides.apply(&mut settings).err().unwrap();
+        assert!(error.starts_with("bad value from --set distrobox\n"));
+    }
+
+    #[test]
+    fn legacy_config_is_migrated_with_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config.conf");
+        let legacy = "server_address|10.0.0.1:31337\ndistrobox|no\ncurrent_files|/home/pyro/a=b/current\ntools|/opt/tools\n";
+        write(&config, legacy).unwrap();
+
+        let settings = Settings::load(&config).unwrap();
+        assert_eq!(settings.server_address, "10.0.0.1:31337");
+        assert!(!settings.distrobox);
+        assert_eq!(
+            settings.current_files,
+            Some(PathBuf::from("/home/pyro/a=b/current"))
+        );
+        assert_eq!(settings.key_file, default_key_file(&config));
+        assert_eq!(
+            read_to_string(dir.path().join("config.conf.bak")).unwrap(),
+            legacy
+        );
+        let migrated = read_to_string(&config).unwrap();
+        assert!(!is_legacy(&migrated));
+        assert_eq!(Settings::load(&config).unwrap(), settings);
+    }
+
+    #[test]
+    fn config_set_validates_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config.conf");
+        let contents = "server_address = \"127.0.0.1:31337\"\n";
+        write(&config, contents).unwrap();
+        let overrides = Overrides::default();
+
+        let missing = dir.path().join("missing").display().to_string();
+        let set_res = config_command(&config, &overrides, &["set", "tools", &missing]);
+        assert_eq!(set_res, Err(format!("{} doesn't exist", missing)));
+        let set_res = config_command(&config, &overrides, &["set", "distrobox", "maybe"]);
+        assert_eq!(set_res, Err(String::from("distrobox has to be yes or no")));
+        assert_eq!(read_to_string(&config).unwrap(), contents);
+
+        let tools = dir.path().display().to_string();
+        config_command(&config, &overrides, &["set", "tools", &tools]).unwrap();
+        assert_eq!(
+            config_command(&config, &overrides, &["get", "tools"]),
+            Ok(format!("{} (config file)", tools))
+        );
+    }
+
+    #[test]
+    fn config_reports_overrides_without_saving_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config.conf");
+        write(
+            &config,
+            "server_address = \"127.0.0.1:31337\"\ndistrobox = false\n",
+        )
+        .unwrap();
+        let mut overrides = Overrides::new(
+            &vec![String::from("distrobox=yes")],
+            Some(String::from("10.0.0.1:443")),
+        )
+        .unwrap();
+        overrides.entries.push((
+            String::from("templatebox"),
+            String::from("env_box"),
+            Source::Env,
+            env_var("templatebox"),
+        ));
+
+        assert_eq!(
+            config_command(&config, &overrides, &["get", "server_address"]),
+            Ok(String::from("10.0.0.1:443 (command line)"))
+        );
+        assert_eq!(
+            config_command(&config, &overrides, &["get", "templatebox"]),
+            Ok(String::from("env_box (environment)"))
+        );
+        let listing = config_command(&config, &overrides, &["list"]).unwrap();
+        assert!(listing.contains("distrobox"));
+        assert!(listing.contains("command line"));
+        assert!(listing.contains("environment"));
+
+        let output = config_command(
+            &config,
+            &overrides,
+            &["set", "server_address", "127.0.0.2:31337"],
+        )
+        .unwrap();
+        assert!(output.contains("still using the command line value, 10.0.0.1:443"));
+        let saved = Settings::load(&config).unwrap();
+        assert_eq!(saved.server_address, "127.0.0.2:31337");
+        assert!(!saved.distrobox);
+        assert_eq!(saved.templatebox, None);
+        let written = read_to_string(&config).unwrap();
+        assert!(!written.contains("10.0.0.1"));
+        assert!(!written.contains("env_box"));
+    }
+}
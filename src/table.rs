@@ -0,0 +1,95 @@
+// the pipe separated tables every listing command prints.
+#[derive(Default, Clone)]
+pub struct Table {
+    pub columns: Vec<usize>,
+    pub headers: String,
+    pub data: Vec<String>,
+}
+
+impl Table {
+    pub fn build(&mut self, data: Vec<String>) -> Table {
+        self.headers = data[0].clone();
+        self.data = data[1..].to_vec();
+        let header_vec: Vec<&str> = self.headers.split("|").collect();
+        for header in header_vec {
+            self.columns.push(header.len());
+        }
+        for data in &self.data {
+            let data_vec: Vec<&str> = data.split("|").collect();
+            for (column, cell) in self.columns.iter_mut().zip(&data_vec) {
+                if cell.len() > *column {
+                    *column = cell.len();
+                }
+            }
+        }
+        for column in self.columns.iter_mut() {
+            if !column.is_multiple_of(2) {
+                *column += 1;
+            }
+        }
+        self.clone()
+    }
+    pub fn get_table(&self) -> String {
+        let mut output = String::new();
+        let mut spacer = String::new();
+        let header_vec: Vec<&str> = self.headers.split("|").collect();
+        for (header, &width) in header_vec.iter().zip(&self.columns) {
+            spacer.push('|');
+            let dashes = "-".repeat(width);
+            spacer.push_str(&dashes);
+            if header.len() < width {
+                let mut padding_needed = width - header.len();
+                if !padding_needed.is_multiple_of(2) {
+                    padding_needed += 1;
+                }
+                let padding = padding_needed / 2;
+                let mut cell = format!("|{}{}{}", " ".repeat(padding), header, " ".repeat(padding));
+                while cell.len() != width {
+                    if cell.len() > width + 1 {
+                        cell.pop();
+                    } else if cell.len() > width + 1 {
+                        cell.push(' ');
+                    } else {
+                        break;
+                    }
+                }
+                output.push_str(&cell);
+            } else {
+                output.push_str(&format!("|{}", header));
+            }
+        }
+        output.push_str("|\n");
+        spacer.push_str("|\n");
+        output.push_str(&spacer);
+        for data_line in self.data.clone() {
+            let line_vec: Vec<&str> = data_line.split("|").collect();
+            for (value, &width) in line_vec.iter().zip(&self.columns) {
+                let cell = if value.len() < width {
+                    let mut padding_needed = width - value.len();
+                    if !padding_needed.is_multiple_of(2) {
+                        padding_needed += 1;
+                    }
+                    let padding = padding_needed / 2;
+                    let mut cell =
+                        format!("|{}{}{}", " ".repeat(padding), value, " ".repeat(padding));
+                    while cell.len() != width + 1 {
+                        if cell.len() > width + 1 {
+                            cell.pop();
+                        } else if cell.len() < width + 1 {
+                            cell.push(' ');
+                        } else {
+                            break;
+                        }
+                    }
+                    cell
+                } else {
+                    format!("|{}", value)
+                };
+                output.push_str(&cell);
+            }
+            output.push_str("|\n");
+            output.push_str(&spacer);
+        }
+        output
+    }
+}
@@ -0,0 +1,208 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read, read_to_string, write};
+use std::path::{Path, PathBuf};
+
+use crate::keys;
+
+// the note templates generate_default_notes knows how to pick between.
+pub const TEMPLATE_NAMES: [&str; 5] = ["internal", "external", "webapp", "phishing", "vishing"];
+
+// hashes of the templates as we last wrote them, so an upgrade can tell a
+// file we put there from one the user has edited since.
+const MANIFEST: &str = ".manifest";
+
+// (name, contents) for every file under default_notes_templates, generated by
+// build.rs.
+include!(concat!(env!("OUT_DIR"), "/bundled_templates.rs"));
+
+#[derive(Debug, Default)]
+pub struct UpgradeReport {
+    pub installed: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub kept: Vec<String>,
+}
+
+impl UpgradeReport {
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} installed, {} updated, {} already current, {} customized and left alone",
+            self.installed.len(),
+            self.updated.len(),
+            self.unchanged.len(),
+            self.kept.len()
+        );
+        for name in &self.kept {
+            summary.push_str(&format!("\nkept your version of {}", name));
+        }
+        summary
+    }
+}
+
+pub fn hash(contents: &[u8]) -> String {
+    keys::to_hex(&Sha256::digest(contents))
+}
+
+pub fn templates_dir(config: &Path) -> PathBuf {
+    config.with_file_name("note_templates")
+}
+
+// the manifest is sha256sum style, "<hash>  <relative path>" per line.
+fn read_manifest(templates: &Path) -> HashMap<String, String> {
+    let mut manifest = HashMap::new();
+    let path = templates.join(MANIFEST);
+    let read_res = read_to_string(path);
+    if read_res.is_err() {
+        return manifest;
+    }
+    for line in read_res.unwrap().lines() {
+        if let Some((hash, name)) = line.split_once("  ") {
+            manifest.insert(name.to_string(), hash.to_string());
+        }
+    }
+    manifest
+}
+
+fn write_manifest(templates: &Path, manifest: &HashMap<String, String>) -> Result<(), String> {
+    let mut names: Vec<&String> = manifest.keys().collect();
+    names.sort();
+    let mut contents = String::new();
+    for name in names {
+        contents.push_str(&format!("{}  {}\n", manifest[name], name));
+    }
+    let path = templates.join(MANIFEST);
+    let write_res = write(&path, contents);
+    if write_res.is_err() {
+        return Err(format!(
+            "error writing template manifest!\n{}",
+            write_res.err().unwrap()
+        ));
+    }
+    Ok(())
+}
+
+pub fn is_installed(templates: &Path) -> bool {
+    let path = templates.join(MANIFEST);
+    path.exists()
+}
+
+// lays the bundled templates down. files that are missing get written,
+// files still matching what we last wrote get the new version, and anything
+// the user has changed is left where it is.
+pub fn upgrade(templates: &Path) -> Result<UpgradeReport, String> {
+    let mut report = UpgradeReport::default();
+    let mut manifest = read_manifest(templates);
+    for (name, contents) in BUNDLED {
+        let bundled_hash = hash(contents);
+        let path = templates.join(name);
+        let existed = path.exists();
+        if existed {
+            let existing_res = read(&path);
+            if existing_res.is_err() {
+                return Err(format!(
+                    "error reading template {}!\n{}",
+                    name,
+                    existing_res.err().unwrap()
+                ));
+            }
+            let existing_hash = hash(&existing_res.unwrap());
+            if existing_hash == bundled_hash {
+                manifest.insert(name.to_string(), bundled_hash);
+                report.unchanged.push(name.to_string());
+                continue;
+            }
+            if manifest.get(name) != Some(&existing_hash) {
+                report.kept.push(name.to_string());
+                continue;
+            }
+        }
+        let parent = path.parent().unwrap().to_path_buf();
+        let dir_res = create_dir_all(&parent);
+        if dir_res.is_err() {
+            return Err(format!(
+                "error creating {}!\n{}",
+                parent.display(),
+                dir_res.err().unwrap()
+            ));
+        }
+        let write_res = write(&path, contents);
+        if write_res.is_err() {
+            return Err(format!(
+                "error writing template {}!\n{}",
+                name,
+                write_res.err().unwrap()
+            ));
+        }
+        if existed {
+            report.updated.push(name.to_string());
+        } else {
+            report.installed.push(name.to_string());
+        }
+        manifest.insert(name.to_string(), bundled_hash);
+    }
+    write_manifest(templates, &manifest)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_template_is_bundled() {
+        for template in TEMPLATE_NAMES {
+            let general = format!("{}/general.md", template);
+            assert!(BUNDLED.iter().any(|(name, _)| *name == general));
+        }
+        let on_disk = walkdir::WalkDir::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/default_notes_templates"
+        ))
+        .into_iter()
+        .filter(|entry| entry.as_ref().unwrap().file_type().is_file())
+        .count();
+        assert_eq!(BUNDLED.len(), on_disk);
+    }
+
+    #[test]
+    fn upgrade_only_replaces_what_we_wrote() {
+        let dir = tempfile::tempdir().unwrap();
+        let templates = dir.path().join("note_templates");
+        let report = upgrade(&templates).unwrap();
+        assert_eq!(report.installed.len(), BUNDLED.len());
+        assert_eq!(read_manifest(&templates).len(), BUNDLED.len());
+
+        let (untouched, edited, missing) = (BUNDLED[0], BUNDLED[1], BUNDLED[2]);
+        // an older bundled version we wrote and the user never touched.
+        write(templates.join(untouched.0), "old template").unwrap();
+        let mut manifest = read_manifest(&templates);
+        manifest.insert(untouched.0.to_string(), hash(b"old template"));
+        write_manifest(&templates, &manifest).unwrap();
+        write(templates.join(edited.0), "my notes").unwrap();
+        std::fs::remove_file(templates.join(missing.0)).unwrap();
+
+        let report = upgrade(&templates).unwrap();
+        assert_eq!(report.updated, vec![untouched.0.to_string()]);
+        assert_eq!(report.kept, vec![edited.0.to_string()]);
+        assert_eq!(report.installed, vec![missing.0.to_string()]);
+        assert_eq!(report.unchanged.len(), BUNDLED.len() - 3);
+        assert!(
+            report
+                .summary()
+                .contains(&format!("kept your version of {}", edited.0))
+        );
+        assert_eq!(read(templates.join(untouched.0)).unwrap(), untouched.1);
+        assert_eq!(
+            read_to_string(templates.join(edited.0)).unwrap(),
+            "my notes"
+        );
+        assert_eq!(read(templates.join(missing.0)).unwrap(), missing.1);
+
+        let manifest = read_manifest(&templates);
+        assert_eq!(manifest[untouched.0], hash(untouched.1));
+        assert_eq!(manifest[missing.0], hash(missing.1));
+        // still what we last wrote, so the user's edit keeps being spotted.
+        assert_eq!(manifest[edited.0], hash(edited.1));
+    }
+}
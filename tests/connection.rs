@@ -0,0 +1,146 @@
+use std::time::Duration;
+use tokio::sync::mpsc::{Sender, channel};
+use tokio::sync::{oneshot, watch};
+
+use tetanus_client::crytpo;
+use tetanus_client::keys::{KeyRing, save_key};
+use tetanus_client::mock_server::MockServer;
+use tetanus_client::network::{ConnectionState, ServerRequest, ServerSession, connection_manager};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start(server: &MockServer, address: &str) -> String {
+    let (listener, address) = server.bind(address).await.unwrap();
+    tokio::spawn(server.clone().serve(listener));
+    address.to_string()
+}
+
+fn keyring_with(dir: &tempfile::TempDir, key: &[u8]) -> KeyRing {
+    let path = dir.path().join("key");
+    save_key(&path, key, "").unwrap();
+    KeyRing::load(&path).unwrap()
+}
+
+// starts a manager and hands back what the cli would hold on to.
+fn manage(
+    address: &str,
+    keyring: KeyRing,
+) -> (Sender<ServerRequest>, watch::Receiver<ConnectionState>) {
+    let (request_tx, request_rx) = channel(16);
+    let (console_tx, mut console_rx) = channel(16);
+    let (state_tx, state_rx) = watch::channel(ConnectionState::Reconnecting);
+    tokio::spawn(async move { while console_rx.recv().await.is_some() {} });
+    tokio::spawn(connection_manager(
+        address.to_string(),
+        String::from("operator"),
+        keyring,
+        None,
+        request_rx,
+        console_tx,
+        state_tx,
+    ));
+    (request_tx, state_rx)
+}
+
+async fn wait_for(state_rx: &mut watch::Receiver<ConnectionState>, wanted: ConnectionState) {
+    tokio::time::timeout(TIMEOUT, state_rx.wait_for(|state| *state == wanted))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+async fn ask(request_tx: &Sender<ServerRequest>, input: &str) -> Result<String, String> {
+    let (response_tx, response_rx) = oneshot::channel();
+    let request = ServerRequest {
+        input: input.to_string(),
+        response_tx: Some(response_tx),
+    };
+    request_tx.send(request).await.unwrap();
+    tokio::time::timeout(TIMEOUT, response_rx)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn server_disconnect_fails_pending_requests() {
+    let key = crytpo::generate_key();
+    let mut server = MockServer::new(&key);
+    server.hang_up_on = Some(String::from("hang up"));
+    let address = start(&server, "127.0.0.1:0").await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let session = ServerSession::connect(&address, "operator", &key, None, console_tx)
+        .await
+        .unwrap();
+    // the timeout is long, so failing inside it means the drop was noticed
+    // rather than waited out.
+    let started = std::time::Instant::now();
+    let error = session
+        .request("hang up", Duration::from_secs(30))
+        .await
+        .err()
+        .unwrap();
+    assert!(error.contains("connection closed before request 1 was answered"));
+    assert!(started.elapsed() < TIMEOUT);
+    let error = session.request("ping", TIMEOUT).await.err().unwrap();
+    assert!(error.contains("connection closed before request 2 was sent"));
+}
+
+#[tokio::test]
+async fn manager_reconnects_after_the_server_hangs_up() {
+    let key = crytpo::generate_key();
+    let mut server = MockServer::new(&key);
+    server.hang_up_on = Some(String::from("hang up"));
+    let address = start(&server, "127.0.0.1:0").await;
+    let dir = tempfile::tempdir().unwrap();
+    let (request_tx, mut state_rx) = manage(&address, keyring_with(&dir, &key));
+
+    wait_for(&mut state_rx, ConnectionState::Connected).await;
+    assert!(ask(&request_tx, "hang up").await.is_err());
+    wait_for(&mut state_rx, ConnectionState::Connected).await;
+    assert_eq!(ask(&request_tx, "ping").await.unwrap(), "echo: ping");
+}
+
+#[tokio::test]
+async fn manager_backs_off_and_resumes_when_the_server_comes_up() {
+    // grab a free port and let it go again so nothing is listening there.
+    let address = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let key = crytpo::generate_key();
+    let dir = tempfile::tempdir().unwrap();
+    let (request_tx, mut state_rx) = manage(&address, keyring_with(&dir, &key));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(*state_rx.borrow(), ConnectionState::Reconnecting);
+    // requests made while we're down are turned away, not queued forever.
+    let error = ask(&request_tx, "ping").await.err().unwrap();
+    assert!(!error.is_empty());
+
+    let server = MockServer::new(&key);
+    start(&server, &address).await;
+    wait_for(&mut state_rx, ConnectionState::Connected).await;
+    assert_eq!(ask(&request_tx, "ping").await.unwrap(), "echo: ping");
+}
+
+#[tokio::test]
+async fn refused_key_falls_back_to_the_previous_one() {
+    // mid rotation: we've moved to a new key but the server only knows the
+    // old one.
+    let old_key = crytpo::generate_key();
+    let dir = tempfile::tempdir().unwrap();
+    let keyring = keyring_with(&dir, &old_key);
+    keyring
+        .replace(crytpo::generate_key(), Duration::from_secs(3600))
+        .unwrap();
+    assert_eq!(keyring.candidates().len(), 2);
+
+    let server = MockServer::new(&old_key);
+    let address = start(&server, "127.0.0.1:0").await;
+    let (request_tx, mut state_rx) = manage(&address, keyring);
+
+    wait_for(&mut state_rx, ConnectionState::Connected).await;
+    assert_eq!(ask(&request_tx, "ping").await.unwrap(), "echo: ping");
+}
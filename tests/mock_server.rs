@@ -0,0 +1,257 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+
+use tetanus_client::crytpo;
+use tetanus_client::mock_server::MockServer;
+use tetanus_client::network::{FrameCodec, ServerSession, enroll, read_frame};
+use tetanus_client::protocol::ServerMessage;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start(server: &MockServer) -> String {
+    let (listener, address) = server.bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(server.clone().serve(listener));
+    address.to_string()
+}
+
+#[tokio::test]
+async fn scripted_command_reaches_the_mock() {
+    let key = crytpo::generate_key();
+    let server = MockServer::new(&key).with_demo_script();
+    let address = start(&server).await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let session = ServerSession::connect(&address, "operator", &key, None, console_tx)
+        .await
+        .unwrap();
+    let response = session.request("ping", TIMEOUT).await.unwrap();
+    assert_eq!(response, "pong");
+
+    let received = server.received();
+    assert!(matches!(received[0], ServerMessage::Hello { .. }));
+    assert!(matches!(
+        &received[1],
+        ServerMessage::Auth { name, .. } if name == "operator"
+    ));
+    assert!(matches!(received[2], ServerMessage::AuthProof { .. }));
+    assert!(received.iter().any(|message| matches!(
+        message,
+        ServerMessage::Command { command, .. } if command == "ping"
+    )));
+}
+
+#[tokio::test]
+async fn unscripted_command_is_echoed_and_recorded() {
+    let key = crytpo::generate_key();
+    let server = MockServer::new(&key);
+    let address = start(&server).await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let session = ServerSession::connect(&address, "operator", &key, None, console_tx)
+        .await
+        .unwrap();
+    let first = session.request("list agents", TIMEOUT).await.unwrap();
+    let second = session.request("whoami", TIMEOUT).await.unwrap();
+    assert_eq!(first, "echo: list agents");
+    assert_eq!(second, "echo: whoami");
+
+    let commands: Vec<(u64, String)> = server
+        .received()
+        .into_iter()
+        .filter_map(|message| match message {
+            ServerMessage::Command { id, command } => Some((id, command)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        commands,
+        vec![
+            (1, String::from("list agents")),
+            (2, String::from("whoami"))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pinned_identity_is_accepted_when_the_server_proves_it() {
+    let key = crytpo::generate_key();
+    let server = MockServer::new(&key).with_demo_script();
+    let address = start(&server).await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let pinned = server.identity_key.clone();
+    let session = ServerSession::connect(&address, "operator", &key, Some(&pinned), console_tx)
+        .await
+        .unwrap();
+    assert_eq!(session.request("ping", TIMEOUT).await.unwrap(), "pong");
+}
+
+#[tokio::test]
+async fn pin_mismatch_is_refused() {
+    let key = crytpo::generate_key();
+    let server = MockServer::new(&key);
+    let address = start(&server).await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let (_, other_identity) = crytpo::generate_identity();
+    let error = ServerSession::connect(
+        &address,
+        "operator",
+        &key,
+        Some(&other_identity),
+        console_tx,
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    assert!(error.to_string().contains("does not match"));
+    assert!(
+        !server
+            .received()
+            .iter()
+            .any(|message| matches!(message, ServerMessage::AuthProof { .. }))
+    );
+}
+
+#[tokio::test]
+async fn impostor_claiming_the_pinned_identity_is_refused() {
+    // the impostor knows our key and the server's public identity, but not
+    // the identity secret, so it can't make the proof.
+    let key = crytpo::generate_key();
+    let real = MockServer::new(&key);
+    let mut impostor = MockServer::new(&key);
+    impostor.identity_key = real.identity_key.clone();
+    let address = start(&impostor).await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let error = ServerSession::connect(
+        &address,
+        "operator",
+        &key,
+        Some(&real.identity_key),
+        console_tx,
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    assert!(
+        error
+            .to_string()
+            .contains("failed to prove it owns identity")
+    );
+    assert!(
+        !impostor
+            .received()
+            .iter()
+            .any(|message| matches!(message, ServerMessage::AuthProof { .. }))
+    );
+}
+
+#[tokio::test]
+async fn enrollment_hands_back_a_proven_identity() {
+    let token = "one-time-token";
+    let mut server = MockServer::new(&crytpo::generate_key());
+    server.enrollment_token = Some(token.to_string());
+    let address = start(&server).await;
+
+    let key = crytpo::generate_key();
+    let identity = enroll(&address, "operator", token, &key).await.unwrap();
+    assert_eq!(identity, server.identity_key);
+    assert_eq!(server.current_key(), key);
+
+    let mut impostor = MockServer::new(&crytpo::generate_key());
+    impostor.enrollment_token = Some(token.to_string());
+    impostor.identity_key = server.identity_key.clone();
+    let address = start(&impostor).await;
+    let error = enroll(&address, "operator", token, &key)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    assert_ne!(impostor.current_key(), key);
+}
+
+#[tokio::test]
+async fn wrong_key_is_dropped_before_auth() {
+    let server = MockServer::new(&crytpo::generate_key());
+    let address = start(&server).await;
+    let (console_tx, _console_rx) = channel(16);
+
+    let wrong = crytpo::generate_key();
+    let connect_res = ServerSession::connect(&address, "operator", &wrong, None, console_tx).await;
+    assert_eq!(
+        connect_res.err().unwrap().kind(),
+        ErrorKind::PermissionDenied
+    );
+    assert!(server.received().is_empty());
+}
+
+#[tokio::test]
+async fn replayed_handshake_is_refused() {
+    // an eavesdropper replays the exact bytes of an earlier handshake on a
+    // new connection. the frames still open, but the challenge is spent.
+    let key = crytpo::generate_key();
+    let server = MockServer::new(&key);
+    let address = start(&server).await;
+
+    let recorded = FrameCodec::client(&key);
+    let (_, public_key) = crytpo::generate_ephemeral();
+    let mut captured = recorded.encode(&ServerMessage::hello()).unwrap();
+    captured.extend(
+        recorded
+            .encode(&ServerMessage::Auth {
+                name: String::from("operator"),
+                challenge: crytpo::generate_challenge(),
+                public_key,
+            })
+            .unwrap(),
+    );
+
+    let mut answers = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(&address).await.unwrap();
+        let codec = FrameCodec::client(&key);
+        stream.write_all(&captured).await.unwrap();
+        assert!(matches!(
+            read_frame(&mut stream, &codec).await.unwrap(),
+            ServerMessage::Hello { .. }
+        ));
+        answers.push(read_frame(&mut stream, &codec).await.unwrap());
+    }
+    assert!(matches!(answers[0], ServerMessage::AuthChallenge { .. }));
+    assert_eq!(
+        answers[1],
+        ServerMessage::AuthResult {
+            accepted: false,
+            message: String::from("replayed challenge"),
+        }
+    );
+}
+
+#[tokio::test]
+async fn pinned_identity_survives_a_restart() {
+    let key = crytpo::generate_key();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mock_identity");
+    let first = MockServer::new(&key).with_identity_file(&path).unwrap();
+    assert!(path.exists());
+
+    let restarted = MockServer::new(&key).with_identity_file(&path).unwrap();
+    assert_eq!(restarted.identity_key, first.identity_key);
+    let address = start(&restarted).await;
+    let (console_tx, _console_rx) = channel(16);
+    let session = ServerSession::connect(
+        &address,
+        "operator",
+        &key,
+        Some(&first.identity_key),
+        console_tx,
+    )
+    .await;
+    assert!(session.is_ok());
+}